@@ -0,0 +1,406 @@
+use super::manifest::value_string;
+use super::mgmt::{guest_config, guest_get, guest_post};
+use clap::ArgMatches;
+use reqwest::header::HeaderMap;
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::error::Error;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+//A network interface as the guest sees it. Addresses are in 10.0.0.5/24 form.
+#[derive(Serialize, Debug, Clone)]
+pub struct Interface {
+    pub name: String,
+    pub mac: Option<String>,
+    pub addresses: Vec<String>,
+}
+
+impl Interface {
+    //Loopback is always there and never what anyone is looking for.
+    pub fn is_loopback(&self) -> bool {
+        self.name == "lo"
+    }
+}
+
+//The first IPv4 address that isn't on loopback, which is the one people usually mean.
+pub fn first_ipv4(interfaces: &[Interface]) -> Option<Ipv4Addr> {
+    interfaces
+        .iter()
+        .filter(|i| !i.is_loopback())
+        .flat_map(|i| i.addresses.iter())
+        .find_map(|a| a.split('/').next()?.parse::<Ipv4Addr>().ok())
+}
+
+//Interfaces and addresses reported from inside the guest. Qemu needs the guest agent running,
+//lxc reads them straight from the container.
+pub async fn interfaces(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    node: &str,
+    kind: &str,
+    vmid: i32,
+) -> Result<Vec<Interface>, Box<dyn Error>> {
+    if kind == "lxc" {
+        let data = guest_get(
+            client,
+            headers,
+            url,
+            node,
+            kind,
+            &vmid.to_string(),
+            "interfaces",
+        )
+        .await?;
+        let interfaces = data
+            .as_array()
+            .unwrap_or(&Vec::new())
+            .iter()
+            .map(|i| Interface {
+                name: text(&i["name"]),
+                mac: i["hwaddr"].as_str().map(|m| m.to_owned()),
+                addresses: ["inet", "inet6"]
+                    .iter()
+                    .filter_map(|key| i[key].as_str())
+                    .map(|a| a.to_owned())
+                    .collect(),
+            })
+            .collect();
+        return Ok(interfaces);
+    }
+    let data = guest_get(
+        client,
+        headers,
+        url,
+        node,
+        kind,
+        &vmid.to_string(),
+        "agent/network-get-interfaces",
+    )
+    .await?;
+    let interfaces = data["result"]
+        .as_array()
+        .unwrap_or(&Vec::new())
+        .iter()
+        .map(|i| Interface {
+            name: text(&i["name"]),
+            mac: i["hardware-address"].as_str().map(|m| m.to_owned()),
+            addresses: i["ip-addresses"]
+                .as_array()
+                .unwrap_or(&Vec::new())
+                .iter()
+                .map(|a| format!("{}/{}", text(&a["ip-address"]), a["prefix"]))
+                .collect(),
+        })
+        .collect();
+    Ok(interfaces)
+}
+
+fn text(value: &Value) -> String {
+    value.as_str().unwrap_or_default().to_owned()
+}
+
+//What start waits for before it calls a guest ready. Without a port that's the qemu guest agent
+//answering a ping, or the container running. With one it's the port accepting a connection on
+//the first IPv4 address the guest reports.
+#[derive(Debug, Clone, Copy)]
+pub struct Readiness {
+    pub port: Option<u16>,
+    pub timeout: Duration,
+}
+
+impl Readiness {
+    //None when neither --wait nor --wait_port is given.
+    pub fn from_args(app: &ArgMatches) -> Result<Option<Readiness>, Box<dyn Error>> {
+        let port = match app.get_one::<String>("Wait_port") {
+            Some(p) => Some(
+                p.parse::<u16>()
+                    .map_err(|_| format!("--wait_port needs to be a port number, not {}", p))?,
+            ),
+            None => None,
+        };
+        if !app.get_flag("Wait") && port.is_none() {
+            return Ok(None);
+        }
+        let timeout = app.get_one::<String>("Wait_timeout").unwrap();
+        let timeout = timeout
+            .parse::<u64>()
+            .map_err(|_| format!("--wait_timeout needs to be seconds, not {}", timeout))?;
+        Ok(Some(Readiness {
+            port,
+            timeout: Duration::from_secs(timeout),
+        }))
+    }
+}
+
+//Whether a qemu config turns the guest agent on. The option is 1, 0 or enabled=1 followed by more
+//settings, and it's off when left out.
+fn agent_enabled(config: &Map<String, Value>) -> bool {
+    let agent = value_string(config.get("agent"));
+    let enabled = agent.split(',').next().unwrap_or_default();
+    enabled.strip_prefix("enabled=").unwrap_or(enabled) == "1"
+}
+
+//Polls a started guest every 2 seconds until it's ready. Hands back the address the port answered
+//on, or the last reason it wasn't ready once the timeout runs out. A qemu guest without the agent
+//turned on can never answer, so that fails straight away instead of at the timeout.
+pub async fn wait_ready(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    node: &str,
+    kind: &str,
+    vmid: i32,
+    readiness: Readiness,
+) -> Result<Option<String>, Box<dyn Error>> {
+    if kind == "qemu" {
+        let config = guest_config(client, headers, url, node, kind, &vmid.to_string()).await?;
+        if !agent_enabled(&config) {
+            return Err("guest agent not enabled in its config".into());
+        }
+    }
+    let deadline = Instant::now() + readiness.timeout;
+    loop {
+        let reason = match check(client, headers, url, node, kind, vmid, readiness.port).await {
+            Ok(address) => return Ok(address),
+            Err(e) => e.to_string(),
+        };
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "not ready after {}s, {}",
+                readiness.timeout.as_secs(),
+                reason
+            )
+            .into());
+        }
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+}
+
+async fn check(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    node: &str,
+    kind: &str,
+    vmid: i32,
+    port: Option<u16>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let id = vmid.to_string();
+    match (kind, port) {
+        ("lxc", None) => {
+            let status = guest_get(client, headers, url, node, kind, &id, "status/current").await?;
+            match status["status"].as_str() {
+                Some("running") => Ok(None),
+                other => Err(format!("container is {}", other.unwrap_or("unknown")).into()),
+            }
+        }
+        (_, None) => {
+            guest_post(
+                client,
+                headers,
+                url,
+                node,
+                kind,
+                &id,
+                "agent/ping",
+                &Map::new(),
+            )
+            .await
+            .map_err(|_| "guest agent isn't answering")?;
+            Ok(None)
+        }
+        (_, Some(port)) => {
+            let ip = first_ipv4(&interfaces(client, headers, url, node, kind, vmid).await?)
+                .ok_or("no IPv4 address reported yet")?;
+            match tokio::time::timeout(Duration::from_secs(3), TcpStream::connect((ip, port))).await
+            {
+                Ok(Ok(_)) => Ok(Some(ip.to_string())),
+                Ok(Err(e)) => Err(format!("{}:{} {}", ip, port, e).into()),
+                Err(_) => Err(format!("{}:{} timed out", ip, port).into()),
+            }
+        }
+    }
+}
+
+//What a command run through the guest agent left behind. Exitcode is missing when it was killed
+//by a signal.
+#[derive(Debug, Clone)]
+pub struct ExecResult {
+    pub exitcode: Option<i64>,
+    pub stdout: String,
+    pub stderr: String,
+    pub truncated: bool,
+}
+
+//Runs a command in a qemu guest and polls every second until it exits. The agent runs it
+//directly, so anything that needs a shell has to be given one, ie sh -c.
+pub async fn exec(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    node: &str,
+    vmid: i32,
+    command: &[String],
+    timeout: Duration,
+) -> Result<ExecResult, Box<dyn Error>> {
+    let id = vmid.to_string();
+    let mut json_data = Map::new();
+    json_data.insert(String::from("command"), json!(command));
+    let started = guest_post(
+        client,
+        headers,
+        url,
+        node,
+        "qemu",
+        &id,
+        "agent/exec",
+        &json_data,
+    )
+    .await?;
+    let pid = started["pid"]
+        .as_i64()
+        .ok_or("the guest agent didn't hand back a pid")?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        let status = guest_get(
+            client,
+            headers,
+            url,
+            node,
+            "qemu",
+            &id,
+            &format!("agent/exec-status?pid={}", pid),
+        )
+        .await?;
+        if status["exited"].as_i64() == Some(1) || status["exited"] == true {
+            return Ok(ExecResult {
+                exitcode: status["exitcode"].as_i64(),
+                stdout: text(&status["out-data"]),
+                stderr: text(&status["err-data"]),
+                truncated: status["out-truncated"].as_i64() == Some(1)
+                    || status["err-truncated"].as_i64() == Some(1),
+            });
+        }
+        if Instant::now() >= deadline {
+            return Err(
+                format!("still running after {}s as pid {}", timeout.as_secs(), pid).into(),
+            );
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+//Proxmox won't pass more than this to file-write in one go.
+pub const MAX_WRITE: usize = 61440;
+
+//Writes text to a file in a qemu guest, replacing what was there. Proxmox base64 encodes it on the
+//way to the agent, so only text can be sent.
+pub async fn file_write(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    node: &str,
+    vmid: i32,
+    path: &str,
+    content: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut json_data = Map::new();
+    json_data.insert(String::from("file"), json!(path));
+    json_data.insert(String::from("content"), json!(content));
+    guest_post(
+        client,
+        headers,
+        url,
+        node,
+        "qemu",
+        &vmid.to_string(),
+        "agent/file-write",
+        &json_data,
+    )
+    .await?;
+    Ok(())
+}
+
+//A file read out of a guest. The agent stops at 16MiB and says so with truncated.
+#[derive(Debug, Clone)]
+pub struct FileContent {
+    pub content: String,
+    pub truncated: bool,
+}
+
+//The path goes in the query string, so it's sent with query() rather than through guest_get.
+pub async fn file_read(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    node: &str,
+    vmid: i32,
+    path: &str,
+) -> Result<FileContent, Box<dyn Error>> {
+    let read_url = format!(
+        "{}/api2/json/nodes/{}/qemu/{}/agent/file-read",
+        url, node, vmid
+    );
+    let response = client
+        .get(read_url)
+        .headers(headers.clone())
+        .query(&[("file", path)])
+        .send()
+        .await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("agent/file-read on VMID {} answered {}", vmid, status).into());
+    }
+    let data = serde_json::de::from_str::<Map<String, Value>>(response.text().await?.as_str())?;
+    let data = data.get("data").cloned().unwrap_or(Value::Null);
+    Ok(FileContent {
+        content: text(&data["content"]),
+        truncated: data["truncated"].as_i64() == Some(1) || data["truncated"] == true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent(value: Value) -> Map<String, Value> {
+        let mut config = Map::new();
+        config.insert(String::from("agent"), value);
+        config
+    }
+
+    #[test]
+    fn reads_every_form_of_the_agent_option() {
+        assert!(agent_enabled(&agent(json!("1"))));
+        assert!(agent_enabled(&agent(json!(1))));
+        assert!(agent_enabled(&agent(json!(
+            "enabled=1,fstrim_cloned_disks=1"
+        ))));
+        assert!(agent_enabled(&agent(json!("1,type=virtio"))));
+        assert!(!agent_enabled(&agent(json!("0"))));
+        assert!(!agent_enabled(&agent(json!("enabled=0"))));
+        assert!(!agent_enabled(&Map::new()));
+    }
+
+    #[test]
+    fn skips_loopback_for_the_first_address() {
+        let interfaces = vec![
+            Interface {
+                name: String::from("lo"),
+                mac: None,
+                addresses: vec![String::from("127.0.0.1/8")],
+            },
+            Interface {
+                name: String::from("eth0"),
+                mac: Some(String::from("aa:bb:cc:dd:ee:ff")),
+                addresses: vec![String::from("fe80::1/64"), String::from("10.0.0.5/24")],
+            },
+        ];
+        assert_eq!(first_ipv4(&interfaces), Some(Ipv4Addr::new(10, 0, 0, 5)));
+        assert_eq!(first_ipv4(&interfaces[..1]), None);
+    }
+}
@@ -16,13 +16,13 @@ pub struct Token {
 }
 pub async fn get_token(
     username: &mut String,
-    password: &String,
-    url: &String,
+    password: &str,
+    url: &str,
 ) -> Result<HeaderMap, Box<dyn Error>> {
     username.push_str("@pam");
     let mut json_data = HashMap::new();
     let user_slice = &username[..];
-    let pass_slice = &password[..];
+    let pass_slice = password;
     json_data.insert("username", user_slice);
     json_data.insert("password", pass_slice);
 
@@ -0,0 +1,762 @@
+use clap::error::ErrorKind;
+use clap::{value_parser, Arg, ArgAction, ArgGroup, ArgMatches, Command};
+
+//The old --action values. They still work but print a note pointing at the subcommand.
+pub const ACTIONS: [&str; 19] = [
+    "clone",
+    "destroy",
+    "bulk_clone",
+    "bulk_destroy",
+    "bulk_start",
+    "bulk_stop",
+    "migrate",
+    "bulk_migrate",
+    "evacuate",
+    "template",
+    "template_build",
+    "config_set",
+    "plan",
+    "apply",
+    "state_list",
+    "state_refresh",
+    "state_destroy",
+    "drift",
+    "import",
+];
+
+//The subcommand that replaced an --action value.
+pub fn subcommand_for(action: &str) -> &str {
+    action.strip_prefix("bulk_").unwrap_or(action)
+}
+
+//Subcommands that only read the local state file and never talk to proxmox.
+const LOCAL: [&str; 1] = ["state_list"];
+
+//Clap won't let global options be required, so the connection options are checked here instead.
+pub fn require_connection(name: &str, app: &ArgMatches) {
+    if LOCAL.contains(&name) {
+        return;
+    }
+    let missing: Vec<&str> = [
+        ("Url", "--url <Url>"),
+        ("Username", "--user <Username>"),
+        ("Password", "--password <Password>"),
+        ("Node", "--node <Node>"),
+    ]
+    .into_iter()
+    .filter(|(id, _)| app.get_one::<String>(id).is_none())
+    .map(|(_, flag)| flag)
+    .collect();
+    if !missing.is_empty() {
+        command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                format!(
+                    "the following required arguments were not provided:\n  {}",
+                    missing.join("\n  ")
+                ),
+            )
+            .exit();
+    }
+}
+
+//Connection and output options. They're global so they can go before or after the subcommand.
+fn global_args() -> Vec<Arg> {
+    vec![
+        Arg::new("Url")
+            .long("url")
+            .short('U')
+            .help("url of the Proxmox host"),
+        Arg::new("Username")
+            .long("user")
+            .short('u')
+            .help("Username for proxmox auth"),
+        Arg::new("Password")
+            .long("password")
+            .short('p')
+            .help("Password for proxmox auth"),
+        Arg::new("Node")
+            .long("node")
+            .short('N')
+            .help("Name of the node"),
+        Arg::new("Output")
+            .long("output")
+            .short('o')
+            .help("Output format. json, ndjson and csv print records on stdout and everything else on stderr.")
+            .default_value("text")
+            .value_parser(["text", "json", "ndjson", "csv"]),
+        Arg::new("State")
+            .long("state")
+            .help("File that keeps track of the guests terrabad created.")
+            .default_value("terrabad.state.json"),
+    ]
+    .into_iter()
+    .map(|arg| arg.global(true))
+    .collect()
+}
+
+fn source() -> Arg {
+    Arg::new("Source")
+        .long("source")
+        .short('s')
+        .help("Source template VMID for action.")
+        .value_parser(value_parser!(i32))
+}
+
+fn destination() -> Arg {
+    Arg::new("Destination")
+        .long("destination")
+        .short('d')
+        .help("Destination template VMID for action. This is only needed for single actions.")
+        .value_parser(value_parser!(i32))
+}
+
+fn name() -> Arg {
+    Arg::new("Name")
+        .long("name")
+        .short('n')
+        .help("Desired name of the created VM. For bulk actions this will add a number.")
+}
+
+fn range_args() -> Vec<Arg> {
+    vec![
+        Arg::new("Min")
+            .long("min")
+            .short('m')
+            .help("First VMID for range. Needed for bulk actions.")
+            .value_parser(value_parser!(i32)),
+        Arg::new("Max")
+            .long("max")
+            .short('M')
+            .help("Last VMID for range. Needed for bulk actions.")
+            .value_parser(value_parser!(i32)),
+    ]
+}
+
+//How bulk actions pace themselves.
+fn bulk_args() -> Vec<Arg> {
+    vec![
+        Arg::new("Threads")
+            .long("threads")
+            .short('t')
+            .help("Number of workers.")
+            .default_value("1"),
+        Arg::new("Adaptive")
+            .long("adaptive")
+            .help("Start bulk jobs with one worker and scale up to --threads based on how the node copes.")
+            .action(ArgAction::SetTrue),
+        Arg::new("Retries")
+            .long("retries")
+            .help("How many times bulk jobs retry connection, 5xx and lock errors.")
+            .default_value("3"),
+        Arg::new("Retry_delay")
+            .long("retry_delay")
+            .help("Milliseconds to wait before the first retry. Doubles every attempt.")
+            .default_value("1000"),
+    ]
+}
+
+//Pacing for actions that go through the guest agent. They aren't retried since running a command
+//twice isn't always harmless.
+fn agent_args() -> Vec<Arg> {
+    bulk_args()
+        .into_iter()
+        .filter(|a| !a.get_id().as_str().starts_with("Retr"))
+        .collect()
+}
+
+//How start waits for guests to come up before calling them done.
+fn wait_args() -> Vec<Arg> {
+    vec![
+        Arg::new("Wait")
+            .long("wait")
+            .help("Wait until each guest is ready: the qemu guest agent answers or the container is running.")
+            .action(ArgAction::SetTrue),
+        Arg::new("Wait_port")
+            .long("wait_port")
+            .help("Wait until this TCP port answers on the address the guest reports instead. Implies --wait."),
+        Arg::new("Wait_timeout")
+            .long("wait_timeout")
+            .help("Seconds to wait for each guest before counting it as failed.")
+            .default_value("300"),
+    ]
+}
+
+fn clone_args() -> Vec<Arg> {
+    vec![
+        Arg::new("Clone_type")
+            .long("clone_type")
+            .short('T')
+            .help("Type of clone. Can either be linked or full.")
+            .default_value("linked")
+            .value_parser(["linked", "full"]),
+        Arg::new("Journal")
+            .long("journal")
+            .help("File bulk_clone keeps its progress in so it can be resumed.")
+            .default_value("terrabad.journal.json"),
+        Arg::new("Resume")
+            .long("resume")
+            .help("Pick up the bulk_clone in the journal where it left off.")
+            .action(ArgAction::SetTrue),
+        Arg::new("Count")
+            .long("count")
+            .short('c')
+            .help("Clone this many times into free VMIDs, within min/max if given."),
+    ]
+}
+
+fn cloudinit_args() -> Vec<Arg> {
+    vec![
+        Arg::new("Ciuser")
+            .long("ciuser")
+            .help("Cloud-init user for clones. Values can use {name}, {index} and {vmid}."),
+        Arg::new("Sshkeys")
+            .long("sshkeys")
+            .help("Public key file or key for cloud-init on clones."),
+        Arg::new("Nameserver")
+            .long("nameserver")
+            .help("DNS server for clones."),
+        Arg::new("Searchdomain")
+            .long("searchdomain")
+            .help("DNS search domain for clones, ie {name}.lab."),
+        Arg::new("Ipconfig0")
+            .long("ipconfig0")
+            .help("Cloud-init ipconfig0 for clones, ie ip=dhcp or ip=10.0.0.{index}/24,gw=10.0.0.1."),
+        Arg::new("Ip_range")
+            .long("ip_range")
+            .help("Hands bulk clones consecutive static addresses starting at this CIDR, ie 10.20.0.10/24.")
+            .conflicts_with("Ipconfig0"),
+        Arg::new("Gateway")
+            .long("gateway")
+            .help("Gateway to go with --ip_range.")
+            .requires("Ip_range"),
+    ]
+}
+
+fn hardware_args() -> Vec<Arg> {
+    vec![
+        Arg::new("Cores")
+            .long("cores")
+            .help("Number of cores per socket for clones or config_set."),
+        Arg::new("Sockets")
+            .long("sockets")
+            .help("Number of CPU sockets. Qemu only."),
+        Arg::new("Memory").long("memory").help("Memory in MiB."),
+        Arg::new("Balloon")
+            .long("balloon")
+            .help("Minimum balloon memory in MiB, 0 disables ballooning. Qemu only."),
+        Arg::new("Net")
+            .long("net")
+            .help("Net device changed by --bridge, --vlan and --firewall.")
+            .default_value("net0"),
+        Arg::new("Bridge")
+            .long("bridge")
+            .help("Bridge for the net device."),
+        Arg::new("Vlan")
+            .long("vlan")
+            .help("VLAN tag for the net device."),
+        Arg::new("Firewall")
+            .long("firewall")
+            .help("Turns the proxmox firewall on the net device on or off.")
+            .value_parser(["0", "1"]),
+        Arg::new("Disk").long("disk").help(
+            "Disk to resize, ie scsi0 or rootfs. Defaults to scsi0 for qemu and rootfs for LXCs.",
+        ),
+        Arg::new("Disk_size")
+            .long("disk_size")
+            .help("New disk size, either absolute (40G) or relative (+10G)."),
+    ]
+}
+
+fn migrate_args() -> Vec<Arg> {
+    vec![
+        Arg::new("Target")
+            .long("target")
+            .help("Node to migrate to. Evacuate accepts a comma separated list."),
+        Arg::new("Target_storage")
+            .long("target_storage")
+            .help("Storage on the target node. Either a single storage or a map like local-lvm:ceph,local:nfs."),
+        Arg::new("Online")
+            .long("online")
+            .help("Live migrate running qemu VMs and restart migrate running LXCs.")
+            .action(ArgAction::SetTrue),
+    ]
+}
+
+fn template_args() -> Vec<Arg> {
+    vec![
+        Arg::new("Image")
+            .long("image")
+            .help("qcow2/raw disk image to build a template from. Can be a local file or an already uploaded volid."),
+        Arg::new("Storage")
+            .long("storage")
+            .help("Storage for the disks of a built template.")
+            .default_value("local-lvm"),
+        Arg::new("Import_storage")
+            .long("import_storage")
+            .help("Storage the disk image gets uploaded to. Needs the import content type.")
+            .default_value("local"),
+    ]
+}
+
+fn manifest() -> Arg {
+    Arg::new("Manifest").long("manifest").short('f').help(
+        "YAML or TOML file describing a lab for plan, apply and drift. Import writes one here.",
+    )
+}
+
+fn auto_approve() -> Arg {
+    Arg::new("Auto_approve")
+        .long("auto_approve")
+        .help("Apply the plan without asking first.")
+        .action(ArgAction::SetTrue)
+}
+
+fn destroy_unmanaged() -> Arg {
+    Arg::new("Destroy_unmanaged")
+        .long("destroy_unmanaged")
+        .help("Also destroy guests with the lab tag that this manifest didn't create.")
+        .action(ArgAction::SetTrue)
+}
+
+fn run() -> Arg {
+    Arg::new("Run")
+        .long("run")
+        .help("Only use state entries from this run id, ie manifest:ctf.")
+}
+
+fn tag() -> Arg {
+    Arg::new("Tag")
+        .long("tag")
+        .help("Only use guests carrying this tag.")
+}
+
+//Either a single VMID with --source or a --min/--max range, for subcommands that do both.
+fn one_or_range(command: Command) -> Command {
+    command
+        .arg(source().help("VMID to act on. Use --min and --max for a range instead."))
+        .args(range_args())
+        .group(
+            ArgGroup::new("Vmids")
+                .args(["Source", "Min"])
+                .required(true),
+        )
+        .mut_arg("Source", |a| a.conflicts_with_all(["Min", "Max"]))
+        .mut_arg("Min", |a| a.requires("Max"))
+        .mut_arg("Max", |a| a.requires("Min"))
+}
+
+fn subcommands() -> Vec<Command> {
+    vec![
+        Command::new("clone")
+            .about("Clone a template once with --destination, or many times with --min/--max or --count.")
+            .arg(source().required_unless_present("Resume"))
+            .arg(destination().conflicts_with_all(["Min", "Max", "Count", "Resume"]))
+            .args(range_args())
+            .arg(name())
+            .args(clone_args())
+            .args(cloudinit_args())
+            .args(hardware_args())
+            .args(bulk_args())
+            .group(
+                ArgGroup::new("Vmids")
+                    .args(["Destination", "Min", "Count", "Resume"])
+                    .multiple(true)
+                    .required(true),
+            )
+            .mut_arg("Min", |a| a.requires("Max"))
+            .mut_arg("Max", |a| a.requires("Min"))
+            .after_help(
+                "Examples:\n  \
+                terrabad -U https://pve:8006 -u root -p pw -N pve clone -s 9000 -d 101 -n web\n  \
+                terrabad -U https://pve:8006 -u root -p pw -N pve clone -s 9000 -m 200 -M 229 -t 8\n  \
+                terrabad -U https://pve:8006 -u root -p pw -N pve clone -s 9000 --count 20\n  \
+                terrabad -U https://pve:8006 -u root -p pw -N pve clone --resume",
+            ),
+        one_or_range(
+            Command::new("destroy")
+                .about("Destroy a guest with --source, or every guest in --min/--max.")
+                .args(bulk_args())
+                .after_help(
+                    "Examples:\n  \
+                    terrabad -U https://pve:8006 -u root -p pw -N pve destroy -s 101\n  \
+                    terrabad -U https://pve:8006 -u root -p pw -N pve destroy -m 200 -M 229 -t 8",
+                ),
+        ),
+        Command::new("start")
+            .about("Start every guest in --min/--max, optionally waiting until they're ready.")
+            .args(range_args().into_iter().map(|a| a.required(true)))
+            .args(bulk_args())
+            .args(wait_args())
+            .after_help(
+                "Examples:\n  \
+                terrabad -U https://pve:8006 -u root -p pw -N pve start -m 200 -M 229 -t 4\n  \
+                terrabad -U https://pve:8006 -u root -p pw -N pve start -m 200 -M 229 --wait_port 22 --wait_timeout 600",
+            ),
+        Command::new("stop")
+            .about("Stop every guest in --min/--max.")
+            .args(range_args().into_iter().map(|a| a.required(true)))
+            .args(bulk_args())
+            .after_help(
+                "Example:\n  terrabad -U https://pve:8006 -u root -p pw -N pve stop -m 200 -M 229 -t 4",
+            ),
+        one_or_range(
+            Command::new("migrate")
+                .about("Migrate a guest with --source, or every guest in --min/--max, to another node.")
+                .args(migrate_args())
+                .mut_arg("Target", |a| a.required(true))
+                .args(bulk_args())
+                .after_help(
+                    "Examples:\n  \
+                    terrabad -U https://pve:8006 -u root -p pw -N pve migrate -s 101 --target pve2 --online\n  \
+                    terrabad -U https://pve:8006 -u root -p pw -N pve migrate -m 200 -M 229 --target pve2 -t 2",
+                ),
+        ),
+        Command::new("evacuate")
+            .about("Migrate every guest off of --node, spread over the --target nodes.")
+            .args(migrate_args())
+            .mut_arg("Target", |a| a.required(true))
+            .args(bulk_args())
+            .after_help(
+                "Example:\n  terrabad -U https://pve:8006 -u root -p pw -N pve1 evacuate --target pve2,pve3 -t 2",
+            ),
+        Command::new("template")
+            .about("Convert an existing guest into a template.")
+            .arg(source().help("VMID to convert.").required(true))
+            .after_help(
+                "Example:\n  terrabad -U https://pve:8006 -u root -p pw -N pve template -s 9000",
+            ),
+        Command::new("template_build")
+            .about("Build a template from a qcow2/raw cloud image.")
+            .arg(destination().help("VMID of the new template.").required(true))
+            .arg(name())
+            .args(template_args())
+            .mut_arg("Image", |a| a.required(true))
+            .args(hardware_args())
+            .after_help(
+                "Example:\n  terrabad -U https://pve:8006 -u root -p pw -N pve template_build -d 9000 -n debian12 --image debian-12-genericcloud-amd64.qcow2",
+            ),
+        one_or_range(
+            Command::new("config_set")
+                .about("Change the hardware of a guest with --source, or every guest in --min/--max.")
+                .args(hardware_args())
+                .after_help(
+                    "Examples:\n  \
+                    terrabad -U https://pve:8006 -u root -p pw -N pve config_set -s 101 --cores 4 --memory 8192\n  \
+                    terrabad -U https://pve:8006 -u root -p pw -N pve config_set -m 200 -M 229 --disk_size +10G",
+                ),
+        ),
+        Command::new("plan")
+            .about("Show what apply would change to make the cluster match a manifest.")
+            .arg(manifest().required(true))
+            .arg(destroy_unmanaged())
+            .after_help(
+                "Example:\n  terrabad -U https://pve:8006 -u root -p pw -N pve plan -f lab.yaml",
+            ),
+        Command::new("apply")
+            .about("Make the cluster match a manifest.")
+            .arg(manifest().required(true))
+            .arg(auto_approve())
+            .arg(destroy_unmanaged())
+            .args(bulk_args())
+            .after_help(
+                "Example:\n  terrabad -U https://pve:8006 -u root -p pw -N pve apply -f lab.yaml -t 4",
+            ),
+        Command::new("state_list")
+            .about("List the guests in the state file.")
+            .arg(run())
+            .after_help(
+                "Example:\n  terrabad -U https://pve:8006 -u root -p pw -N pve state_list --run manifest:ctf",
+            ),
+        Command::new("state_refresh")
+            .about("Update the state file with guests that moved, were renamed or are gone.")
+            .after_help(
+                "Example:\n  terrabad -U https://pve:8006 -u root -p pw -N pve state_refresh",
+            ),
+        Command::new("state_destroy")
+            .about("Destroy the guests in the state file.")
+            .arg(run())
+            .arg(auto_approve().help("Destroy without asking first."))
+            .after_help(
+                "Example:\n  terrabad -U https://pve:8006 -u root -p pw -N pve state_destroy --run run-1700000000-4242",
+            ),
+        Command::new("drift")
+            .about("Show guests that were changed by hand since they were provisioned.")
+            .arg(manifest())
+            .arg(run())
+            .after_help(
+                "Example:\n  terrabad -U https://pve:8006 -u root -p pw -N pve drift -f lab.yaml -o json",
+            ),
+        Command::new("import")
+            .about("Adopt guests that weren't made by terrabad into the state and a manifest.")
+            .arg(
+                source()
+                    .help("VMID or comma separated VMIDs to import.")
+                    .value_delimiter(','),
+            )
+            .args(range_args())
+            .arg(tag())
+            .arg(manifest())
+            .group(
+                ArgGroup::new("Selection")
+                    .args(["Source", "Min", "Tag"])
+                    .multiple(true)
+                    .required(true),
+            )
+            .mut_arg("Min", |a| a.requires("Max"))
+            .mut_arg("Max", |a| a.requires("Min"))
+            .after_help(
+                "Example:\n  terrabad -U https://pve:8006 -u root -p pw -N pve import --tag ctf -f ctf.yaml",
+            ),
+        Command::new("list")
+            .about("List the guests in the whole cluster with their status and usage.")
+            .args(range_args())
+            .arg(tag())
+            .arg(
+                Arg::new("Filter_node")
+                    .long("filter_node")
+                    .help("Only list guests on this node. --node is still the one terrabad connects to."),
+            )
+            .arg(
+                Arg::new("Status")
+                    .long("status")
+                    .help("Only list guests with this status, ie running or stopped."),
+            )
+            .arg(
+                Arg::new("Sort")
+                    .long("sort")
+                    .help("Column to sort by.")
+                    .value_parser(super::list::COLUMNS)
+                    .default_value("vmid"),
+            )
+            .arg(
+                Arg::new("Reverse")
+                    .long("reverse")
+                    .help("Sort the other way round.")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("Columns")
+                    .long("columns")
+                    .help("Comma separated columns to show. Defaults to all of them."),
+            )
+            .after_help(
+                "Examples:\n  \
+                terrabad -U https://pve:8006 -u root -p pw -N pve list --status running --sort mem --reverse\n  \
+                terrabad -U https://pve:8006 -u root -p pw -N pve list --tag ctf --columns vmid,name,node -o csv",
+            ),
+        one_or_range(
+            Command::new("exec")
+                .about("Run a command through the qemu guest agent in a guest with --source, or every guest in --min/--max.")
+                .args(agent_args())
+                .arg(
+                    Arg::new("Exec_timeout")
+                        .long("exec_timeout")
+                        .help("Seconds to let the command run in each guest before giving up on it.")
+                        .default_value("60"),
+                )
+                .arg(
+                    Arg::new("Command")
+                        .help("Command to run and its arguments, after --. Use sh -c for anything that needs a shell.")
+                        .num_args(1..)
+                        .last(true)
+                        .required(true),
+                )
+                .after_help(
+                    "Examples:\n  \
+                    terrabad -U https://pve:8006 -u root -p pw -N pve exec -m 200 -M 229 -t 8 -- systemctl restart nginx\n  \
+                    terrabad -U https://pve:8006 -u root -p pw -N pve exec -s 201 -o json -- sh -c 'cat /etc/os-release'",
+                ),
+        ),
+        one_or_range(
+            Command::new("file_write")
+                .about("Write a file through the qemu guest agent in a guest with --source, or every guest in --min/--max.")
+                .args(agent_args())
+                .arg(
+                    Arg::new("Path")
+                        .long("path")
+                        .help("File to write in the guest. Can use {name}, {index} and {vmid}.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("Content")
+                        .long("content")
+                        .help("Text to write. Can use {name}, {index} and {vmid}."),
+                )
+                .arg(
+                    Arg::new("From")
+                        .long("from")
+                        .help("Local text file to write instead of --content. Sent as is unless --template is given."),
+                )
+                .arg(
+                    Arg::new("Template")
+                        .long("template")
+                        .help("Fill {name}, {index} and {vmid} in the --from file too.")
+                        .conflicts_with("Content")
+                        .action(ArgAction::SetTrue),
+                )
+                .group(
+                    ArgGroup::new("Data")
+                        .args(["Content", "From"])
+                        .required(true),
+                )
+                .after_help(
+                    "Examples:\n  \
+                    terrabad -U https://pve:8006 -u root -p pw -N pve file_write -m 200 -M 229 --path /root/flag.txt --content 'flag{{vmid}-{index}}'\n  \
+                    terrabad -U https://pve:8006 -u root -p pw -N pve file_write -s 201 --path /etc/motd --from motd.txt\n  \
+                    terrabad -U https://pve:8006 -u root -p pw -N pve file_write -m 200 -M 229 --path /etc/lab.conf --from lab.conf --template",
+                ),
+        ),
+        one_or_range(
+            Command::new("file_read")
+                .about("Read a file through the qemu guest agent from a guest with --source, or every guest in --min/--max.")
+                .args(agent_args())
+                .arg(
+                    Arg::new("Path")
+                        .long("path")
+                        .help("File to read in the guest. Can use {name}, {index} and {vmid}.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("To")
+                        .long("to")
+                        .help("Save each copy locally instead of printing it. Use {vmid} or {name} to keep them apart."),
+                )
+                .after_help(
+                    "Examples:\n  \
+                    terrabad -U https://pve:8006 -u root -p pw -N pve file_read -s 201 --path /etc/hostname\n  \
+                    terrabad -U https://pve:8006 -u root -p pw -N pve file_read -m 200 -M 229 --path /var/log/setup.log --to logs/{vmid}.log",
+                ),
+        ),
+        Command::new("export")
+            .about("Write an Ansible inventory or ssh_config for guests picked by VMID, range or tag.")
+            .arg(
+                source()
+                    .help("VMID or comma separated VMIDs to export.")
+                    .value_delimiter(','),
+            )
+            .args(range_args())
+            .arg(tag())
+            .group(
+                ArgGroup::new("Selection")
+                    .args(["Source", "Min", "Tag"])
+                    .multiple(true)
+                    .required(true),
+            )
+            .mut_arg("Min", |a| a.requires("Max"))
+            .mut_arg("Max", |a| a.requires("Min"))
+            .arg(
+                Arg::new("Format")
+                    .long("format")
+                    .help("What to write.")
+                    .value_parser(["ini", "yaml", "ssh_config"])
+                    .default_value("ini"),
+            )
+            .arg(
+                Arg::new("Group_by")
+                    .long("group_by")
+                    .help("Put hosts in Ansible groups named after their tags or pool.")
+                    .value_parser(["tag", "pool", "none"])
+                    .default_value("tag"),
+            )
+            .arg(
+                Arg::new("Ssh_user")
+                    .long("ssh_user")
+                    .help("User to log in as, written as ansible_user or User."),
+            )
+            .arg(
+                Arg::new("File")
+                    .long("file")
+                    .help("Write to this file instead of stdout."),
+            )
+            .after_help(
+                "Examples:\n  \
+                terrabad -U https://pve:8006 -u root -p pw -N pve export --tag ctf --ssh_user root --file hosts.ini\n  \
+                terrabad -U https://pve:8006 -u root -p pw -N pve export -m 200 -M 229 --format ssh_config >> ~/.ssh/config",
+            ),
+        Command::new("show")
+            .about("Show status, config, snapshots, disks, network and addresses of one guest.")
+            .arg(source().help("VMID to show.").required(true))
+            .after_help(
+                "Examples:\n  \
+                terrabad -U https://pve:8006 -u root -p pw -N pve show -s 150\n  \
+                terrabad -U https://pve:8006 -u root -p pw -N pve show -s 150 -o json",
+            ),
+    ]
+}
+
+//Every option at the top level, the way terrabad took them before subcommands. Hidden from help
+//and only used together with --action.
+fn legacy_args() -> Vec<Arg> {
+    let mut args = vec![
+        Arg::new("Action")
+            .long("action")
+            .short('a')
+            .help("Deprecated, use a subcommand instead.")
+            .value_parser(ACTIONS)
+            .requires_if("bulk_clone", "Legacy_clone_jobs"),
+        name(),
+        //Import takes a comma separated list, everything else reads the first VMID.
+        source().value_delimiter(','),
+        destination(),
+        manifest().required_if_eq_any([("Action", "plan"), ("Action", "apply")]),
+        auto_approve(),
+        destroy_unmanaged(),
+        run(),
+        tag(),
+    ];
+    //bulk_clone can pick its VMIDs with --count instead, see the Legacy_clone_jobs group.
+    args.extend(range_args().into_iter().map(|a| {
+        let other = match a.get_id() == "Min" {
+            true => "Max",
+            false => "Min",
+        };
+        a.requires(other).required_if_eq_any([
+            ("Action", "bulk_destroy"),
+            ("Action", "bulk_start"),
+            ("Action", "bulk_stop"),
+            ("Action", "bulk_migrate"),
+        ])
+    }));
+    args.extend(clone_args());
+    args.extend(bulk_args());
+    args.extend(wait_args());
+    args.extend(cloudinit_args());
+    args.extend(hardware_args());
+    args.extend(
+        migrate_args()
+            .into_iter()
+            .map(|a| match a.get_id() == "Target" {
+                true => a.required_if_eq_any([
+                    ("Action", "migrate"),
+                    ("Action", "bulk_migrate"),
+                    ("Action", "evacuate"),
+                ]),
+                false => a,
+            }),
+    );
+    args.extend(
+        template_args()
+            .into_iter()
+            .map(|a| match a.get_id() == "Image" {
+                true => a.required_if_eq("Action", "template_build"),
+                false => a,
+            }),
+    );
+    args.into_iter().map(|a| a.hide(true)).collect()
+}
+
+pub fn command() -> Command {
+    Command::new("terrabad")
+        .author("numonce")
+        .about("A tool for managing proxmox functions written in pure rust.")
+        .version("1.0.0")
+        .args(global_args())
+        .args(legacy_args())
+        .group(
+            ArgGroup::new("Legacy_clone_jobs")
+                .args(["Min", "Count", "Resume"])
+                .multiple(true),
+        )
+        .subcommands(subcommands())
+}
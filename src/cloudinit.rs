@@ -0,0 +1,215 @@
+use super::mgmt::set_config;
+use clap::ArgMatches;
+use reqwest::header::HeaderMap;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::path::Path;
+
+//The config keys render can set, so a manifest diff on any of them knows to apply cloud-init again.
+pub const KEYS: [&str; 5] = [
+    "ciuser",
+    "sshkeys",
+    "nameserver",
+    "searchdomain",
+    "ipconfig0",
+];
+
+//Holds the cloud-init settings given on the command line. Every value can use {name}, {index}
+//and {vmid} which get filled in per clone, ie --ipconfig0 ip=dhcp --searchdomain {name}.lab
+//It's also the cloudinit section of a manifest.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CloudInit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ciuser: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sshkeys: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nameserver: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub searchdomain: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipconfig0: Option<String>,
+}
+
+impl CloudInit {
+    pub fn from_args(app: &ArgMatches) -> Result<CloudInit, Box<dyn Error>> {
+        let mut cloudinit = CloudInit {
+            ciuser: app.get_one::<String>("Ciuser").cloned(),
+            sshkeys: app.get_one::<String>("Sshkeys").cloned(),
+            nameserver: app.get_one::<String>("Nameserver").cloned(),
+            searchdomain: app.get_one::<String>("Searchdomain").cloned(),
+            ipconfig0: app.get_one::<String>("Ipconfig0").cloned(),
+        };
+        cloudinit.read_sshkeys()?;
+        Ok(cloudinit)
+    }
+
+    //Sshkeys can either be a path to a public key file or the key itself.
+    pub fn read_sshkeys(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(k) = &self.sshkeys {
+            if Path::new(k).is_file() {
+                self.sshkeys = Some(std::fs::read_to_string(k)?);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ciuser.is_none()
+            && self.sshkeys.is_none()
+            && self.nameserver.is_none()
+            && self.searchdomain.is_none()
+            && self.ipconfig0.is_none()
+    }
+
+    //Builds the config body for one clone. Lxcs don't run cloud-init, but nameserver and
+    //searchdomain are plain config keys there so those still get applied.
+    pub fn render(&self, kind: &str, name: &str, index: i32, vmid: i32) -> Map<String, Value> {
+        let fill = |value: &String| fill(value, name, index, vmid);
+        let mut json_data = Map::new();
+        if let Some(n) = &self.nameserver {
+            json_data.insert("nameserver".to_string(), Value::String(fill(n)));
+        }
+        if let Some(s) = &self.searchdomain {
+            json_data.insert("searchdomain".to_string(), Value::String(fill(s)));
+        }
+        if kind != "qemu" {
+            return json_data;
+        }
+        if let Some(u) = &self.ciuser {
+            json_data.insert("ciuser".to_string(), Value::String(fill(u)));
+        }
+        if let Some(k) = &self.sshkeys {
+            json_data.insert(
+                "sshkeys".to_string(),
+                Value::String(encode_sshkeys(fill(k).trim())),
+            );
+        }
+        if let Some(i) = &self.ipconfig0 {
+            json_data.insert("ipconfig0".to_string(), Value::String(fill(i)));
+        }
+        json_data
+    }
+}
+
+//Fills in {name}, {index} and {vmid}. Also used for what file_write puts in each guest.
+pub fn fill(value: &str, name: &str, index: i32, vmid: i32) -> String {
+    value
+        .replace("{name}", name)
+        .replace("{index}", &index.to_string())
+        .replace("{vmid}", &vmid.to_string())
+}
+
+//Applies the rendered cloud-init settings to a freshly cloned guest.
+#[allow(clippy::too_many_arguments)]
+pub async fn apply(
+    cloudinit: &CloudInit,
+    client: &Client,
+    token: &HeaderMap,
+    url: &str,
+    nodename: &str,
+    kind: &str,
+    name: &str,
+    index: i32,
+    vmid: i32,
+) -> Result<(), Box<dyn Error>> {
+    let json_data = cloudinit.render(kind, name, index, vmid);
+    if json_data.is_empty() {
+        return Ok(());
+    }
+    set_config(
+        client,
+        token,
+        url,
+        nodename,
+        kind,
+        &vmid.to_string(),
+        &json_data,
+    )
+    .await?;
+    say!("Cloud-init applied to VMID {}", vmid);
+    Ok(())
+}
+
+//Proxmox wants sshkeys url encoded even when it's sent as json, and it doesn't accept + for
+//spaces, so this does the encoding by hand.
+fn encode_sshkeys(keys: &str) -> String {
+    let mut encoded = String::new();
+    for byte in keys.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+//Undoes encode_sshkeys, or any other percent encoding proxmox stored the keys with.
+pub fn decode_sshkeys(keys: &str) -> String {
+    let bytes = keys.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_every_placeholder() {
+        assert_eq!(
+            fill("ip=10.0.0.{index}/24 {name}.lab {vmid}", "web3", 3, 203),
+            "ip=10.0.0.3/24 web3.lab 203"
+        );
+        assert_eq!(fill("{index}{index}", "", 7, 0), "77");
+        assert_eq!(fill("no placeholders", "web", 1, 201), "no placeholders");
+    }
+
+    #[test]
+    fn renders_per_guest_and_per_type() {
+        let cloudinit = CloudInit {
+            ciuser: Some(String::from("{name}")),
+            sshkeys: Some(String::from("ssh-ed25519 AAAA me@host\n")),
+            searchdomain: Some(String::from("lab{index}.local")),
+            ipconfig0: Some(String::from("ip=dhcp")),
+            ..Default::default()
+        };
+        let qemu = cloudinit.render("qemu", "web2", 2, 202);
+        assert_eq!(qemu["ciuser"], "web2");
+        assert_eq!(qemu["searchdomain"], "lab2.local");
+        assert_eq!(qemu["sshkeys"], "ssh-ed25519%20AAAA%20me%40host");
+        assert_eq!(qemu["ipconfig0"], "ip=dhcp");
+        let lxc = cloudinit.render("lxc", "web2", 2, 202);
+        assert_eq!(lxc.len(), 1);
+        assert_eq!(lxc["searchdomain"], "lab2.local");
+    }
+
+    #[test]
+    fn sshkeys_survive_encoding() {
+        let keys = "ssh-ed25519 AAAA+/= me@host\nssh-rsa BBBB you@host";
+        assert_eq!(decode_sshkeys(&encode_sshkeys(keys)), keys);
+        assert_eq!(decode_sshkeys("100%"), "100%");
+        assert_eq!(decode_sshkeys("%zz%41"), "%zzA");
+    }
+}
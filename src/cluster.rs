@@ -0,0 +1,133 @@
+use reqwest::header::HeaderMap;
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::error::Error;
+
+//Handles /cluster/resources?type=vm, which lists every guest in the cluster no matter the node.
+#[derive(Deserialize, Debug)]
+pub struct ResourceData {
+    pub data: Vec<Resource>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Resource {
+    pub vmid: i32,
+    pub node: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub name: Option<String>,
+    //Tags come back as a single ; separated string.
+    pub tags: Option<String>,
+    pub pool: Option<String>,
+    //1 for templates, left out or 0 otherwise.
+    #[serde(default)]
+    pub template: Option<u8>,
+    #[serde(default)]
+    pub status: Option<String>,
+    //Fraction of maxcpu in use, so 0.5 on a 4 core guest is 2 cores busy.
+    #[serde(default)]
+    pub cpu: Option<f64>,
+    #[serde(default)]
+    pub maxcpu: Option<f64>,
+    //Bytes.
+    #[serde(default)]
+    pub mem: Option<u64>,
+    #[serde(default)]
+    pub maxmem: Option<u64>,
+    //Seconds.
+    #[serde(default)]
+    pub uptime: Option<u64>,
+    //Set while a task holds the guest, ie clone while it's still being cloned.
+    #[serde(default)]
+    pub lock: Option<String>,
+}
+
+impl Resource {
+    pub fn is_template(&self) -> bool {
+        self.template == Some(1)
+    }
+
+    pub fn tag_list(&self) -> Vec<String> {
+        match &self.tags {
+            Some(t) => t
+                .split([';', ',', ' '])
+                .filter(|tag| !tag.is_empty())
+                .map(|tag| tag.to_owned())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+pub async fn get_resources(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+) -> Result<Vec<Resource>, Box<dyn Error>> {
+    let resource_url = format!("{}/api2/json/cluster/resources?type=vm", url);
+    let text = client
+        .get(resource_url)
+        .headers(headers.clone())
+        .send()
+        .await?
+        .text()
+        .await?;
+    let resources = serde_json::de::from_str::<ResourceData>(text.as_str())?;
+    Ok(resources.data)
+}
+
+#[derive(Deserialize, Debug)]
+struct NextIdData {
+    data: String,
+}
+
+//Finds count VMIDs nobody is using. Without a range this starts at whatever /cluster/nextid hands
+//out, since it only ever returns one id, and then skips over anything in /cluster/resources.
+pub async fn allocate_vmids(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    count: usize,
+    min: Option<i32>,
+    max: Option<i32>,
+) -> Result<Vec<i32>, Box<dyn Error>> {
+    let start = match min {
+        Some(m) => m,
+        None => {
+            let nextid_url = format!("{}/api2/json/cluster/nextid", url);
+            let text = client
+                .get(nextid_url)
+                .headers(headers.clone())
+                .send()
+                .await?
+                .text()
+                .await?;
+            serde_json::de::from_str::<NextIdData>(text.as_str())?
+                .data
+                .parse::<i32>()?
+        }
+    };
+    //Proxmox only allows VMIDs up to 999999999.
+    let end = max.unwrap_or(999_999_999);
+    let used: HashSet<i32> = get_resources(client, headers, url)
+        .await?
+        .iter()
+        .map(|r| r.vmid)
+        .collect();
+    let vmids: Vec<i32> = (start..end + 1)
+        .filter(|vmid| !used.contains(vmid))
+        .take(count)
+        .collect();
+    if vmids.len() < count {
+        return Err(format!(
+            "Only {} free VMIDs between {} and {}, {} needed",
+            vmids.len(),
+            start,
+            end,
+            count
+        )
+        .into());
+    }
+    Ok(vmids)
+}
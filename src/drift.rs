@@ -0,0 +1,229 @@
+use super::cluster::{get_resources, Resource};
+use super::manifest::{diff_config, Diff, Manifest};
+use super::mgmt::guest_config;
+use super::output::{self, Format};
+use super::state::StateFile;
+use clap::ArgMatches;
+use reqwest::ClientBuilder;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::error::Error;
+
+//What one VMID looks like compared to what was provisioned. Status is one of ok, drifted, missing
+//or extra.
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub vmid: i32,
+    pub name: String,
+    pub node: String,
+    pub status: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub diffs: Vec<Diff>,
+}
+
+impl Report {
+    fn new(vmid: i32, name: &str, node: &str, status: &str, diffs: Vec<Diff>) -> Report {
+        Report {
+            vmid,
+            name: name.to_owned(),
+            node: node.to_owned(),
+            status: status.to_owned(),
+            diffs,
+        }
+    }
+}
+
+//Compares every guest in the manifest against its live config. Guests with the lab tag that the
+//manifest doesn't know about are reported as extra.
+async fn manifest_drift(
+    app: &ArgMatches,
+    manifest: &Manifest,
+    resources: &[Resource],
+    client: &reqwest::Client,
+    token: &reqwest::header::HeaderMap,
+    url: &str,
+) -> Result<Vec<Report>, Box<dyn Error>> {
+    let nodename = app.get_one::<String>("Node").unwrap();
+    let desired = manifest.desired(nodename);
+    let wanted: HashSet<i32> = desired.iter().map(|d| d.vmid).collect();
+    let mut reports = Vec::new();
+    for guest in desired {
+        let resource = match resources.iter().find(|r| r.vmid == guest.vmid) {
+            Some(r) => r,
+            None => {
+                reports.push(Report::new(
+                    guest.vmid,
+                    &guest.name,
+                    &guest.node,
+                    "missing",
+                    Vec::new(),
+                ));
+                continue;
+            }
+        };
+        let config = guest_config(
+            client,
+            token,
+            url,
+            &resource.node,
+            &resource.kind,
+            &guest.vmid.to_string(),
+        )
+        .await?;
+        let mut diffs = diff_config(&guest, &manifest.lab, &resource.kind, &config);
+        //Clones always come with net0, so losing it is drift even if the manifest doesn't set it.
+        //Imported guests have no template and may never have had one.
+        if guest.group.template.is_some()
+            && !config.contains_key("net0")
+            && !diffs.iter().any(|d| d.key.starts_with("net0"))
+        {
+            diffs.push(Diff {
+                key: String::from("net0"),
+                current: String::from("missing"),
+                wanted: String::from("present"),
+            });
+        }
+        if let Some(pool) = &guest.group.pool {
+            if resource.pool.as_ref() != Some(pool) {
+                diffs.push(Diff {
+                    key: String::from("pool"),
+                    current: resource.pool.clone().unwrap_or_default(),
+                    wanted: pool.to_owned(),
+                });
+            }
+        }
+        let status = if diffs.is_empty() { "ok" } else { "drifted" };
+        reports.push(Report::new(
+            guest.vmid,
+            &guest.name,
+            &resource.node,
+            status,
+            diffs,
+        ));
+    }
+    for resource in resources {
+        if wanted.contains(&resource.vmid) || !resource.tag_list().contains(&manifest.lab) {
+            continue;
+        }
+        reports.push(Report::new(
+            resource.vmid,
+            &resource.name.clone().unwrap_or_default(),
+            &resource.node,
+            "extra",
+            Vec::new(),
+        ));
+    }
+    Ok(reports)
+}
+
+//Without a manifest the state file is what was provisioned. It only records names and nodes, so
+//this catches missing, moved and renamed guests.
+fn state_drift(app: &ArgMatches, resources: &[Resource]) -> Result<Vec<Report>, Box<dyn Error>> {
+    let state = StateFile::from_args(app).load()?;
+    let run = app.get_one::<String>("Run");
+    let mut reports = Vec::new();
+    for entry in state
+        .resources
+        .iter()
+        .filter(|e| run.is_none_or(|r| &e.run == r))
+    {
+        let resource = match resources.iter().find(|r| r.vmid == entry.vmid) {
+            Some(r) => r,
+            None => {
+                reports.push(Report::new(
+                    entry.vmid,
+                    &entry.name,
+                    &entry.node,
+                    "missing",
+                    Vec::new(),
+                ));
+                continue;
+            }
+        };
+        let mut diffs = Vec::new();
+        if resource.node != entry.node {
+            diffs.push(Diff {
+                key: String::from("node"),
+                current: resource.node.clone(),
+                wanted: entry.node.clone(),
+            });
+        }
+        let name = resource.name.clone().unwrap_or_default();
+        if name != entry.name {
+            diffs.push(Diff {
+                key: String::from("name"),
+                current: name,
+                wanted: entry.name.clone(),
+            });
+        }
+        let status = if diffs.is_empty() { "ok" } else { "drifted" };
+        reports.push(Report::new(
+            entry.vmid,
+            &entry.name,
+            &resource.node,
+            status,
+            diffs,
+        ));
+    }
+    Ok(reports)
+}
+
+fn print_reports(reports: &[Report]) {
+    let mut in_sync = 0;
+    for report in reports {
+        match report.status.as_str() {
+            "ok" => in_sync += 1,
+            "missing" => say!("{} {}: missing", report.vmid, report.name),
+            "extra" => say!(
+                "{} {}: extra guest on {}",
+                report.vmid,
+                report.name,
+                report.node
+            ),
+            _ => {
+                say!("{} {}: drifted", report.vmid, report.name);
+                for diff in &report.diffs {
+                    say!(
+                        "    {}: {:?}, expected {:?}",
+                        diff.key,
+                        diff.current,
+                        diff.wanted
+                    );
+                }
+            }
+        }
+    }
+    say!(
+        "{} of {} guests in sync.",
+        in_sync,
+        reports.iter().filter(|r| r.status != "extra").count()
+    );
+}
+
+//Reports how the cluster differs from the manifest, or from the state file when no manifest is
+//given.
+pub async fn drift(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let resources = get_resources(&client, &token, &url).await?;
+    let reports = match app.get_one::<String>("Manifest") {
+        Some(m) => {
+            let manifest = Manifest::load(m)?;
+            manifest_drift(&app, &manifest, &resources, &client, &token, &url).await?
+        }
+        None => state_drift(&app, &resources)?,
+    };
+    match output::format() {
+        Format::Text => print_reports(&reports),
+        _ => output::records(&reports)?,
+    }
+    Ok(())
+}
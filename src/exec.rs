@@ -0,0 +1,146 @@
+use super::agent::{self, ExecResult};
+use super::cluster::{get_resources, Resource};
+use super::output;
+use super::summary::Summary;
+use super::throttle::Throttle;
+use clap::ArgMatches;
+use reqwest::ClientBuilder;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::Duration;
+
+//The VMIDs to work on, from --source or --min/--max.
+pub fn vmids(app: &ArgMatches) -> Vec<i32> {
+    if let Some(source) = app.get_one::<i32>("Source") {
+        return vec![*source];
+    }
+    let max = *app.get_one::<i32>("Max").unwrap();
+    let min = *app.get_one::<i32>("Min").unwrap();
+    (min..max + 1).collect()
+}
+
+//Why a guest can't be reached through the qemu guest agent, if it can't.
+pub fn unreachable(resource: Option<&Resource>) -> Option<String> {
+    match resource {
+        None => Some(String::from("doesn't exist")),
+        Some(r) if r.kind != "qemu" => Some(String::from("not a qemu VM, lxc has no guest agent")),
+        Some(r) if r.is_template() => Some(String::from("is a template")),
+        Some(r) if r.status.as_deref() != Some("running") => Some(String::from("isn't running")),
+        Some(_) => None,
+    }
+}
+
+//Everything a guest printed, indented under a line saying how it went.
+fn print_result(vmid: i32, result: &ExecResult) {
+    let mut text = match result.exitcode {
+        Some(code) => format!("VMID {} exited with {}", vmid, code),
+        None => format!("VMID {} was killed", vmid),
+    };
+    for line in result.stdout.lines() {
+        text.push_str(&format!("\n  {}", line));
+    }
+    if !result.stderr.is_empty() {
+        text.push_str("\n  stderr:");
+        for line in result.stderr.lines() {
+            text.push_str(&format!("\n    {}", line));
+        }
+    }
+    if result.truncated {
+        text.push_str("\n  (output was cut off by the guest agent)");
+    }
+    say!("{}", text);
+}
+
+//The fields exec adds to its records. Always all of them, so csv gets the same header for guests
+//that never ran the command.
+fn extra(result: Option<&ExecResult>) -> Map<String, Value> {
+    let mut extra = Map::new();
+    extra.insert(
+        String::from("exitcode"),
+        json!(result.and_then(|r| r.exitcode)),
+    );
+    extra.insert(String::from("stdout"), json!(result.map(|r| &r.stdout)));
+    extra.insert(String::from("stderr"), json!(result.map(|r| &r.stderr)));
+    extra
+}
+
+//Runs a command in every qemu guest of --source or --min/--max through the guest agent, no ssh
+//needed. A guest counts as failed when the command exits with anything but 0.
+pub async fn exec(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let command: Vec<String> = app
+        .get_many::<String>("Command")
+        .unwrap_or_else(|| panic!("exec needs a command after --"))
+        .cloned()
+        .collect();
+    let timeout = app.get_one::<String>("Exec_timeout").unwrap();
+    let timeout = Duration::from_secs(
+        timeout
+            .parse::<u64>()
+            .map_err(|_| format!("--exec_timeout needs to be seconds, not {}", timeout))?,
+    );
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let throttle = Throttle::from_args(&app, &client, &token, &url).await?;
+    let resources: HashMap<i32, Resource> = get_resources(&client, &token, &url)
+        .await?
+        .into_iter()
+        .map(|r| (r.vmid, r))
+        .collect();
+    let jobs = vmids(&app);
+    let summary = Summary::with_progress("exec", jobs.len());
+    let fallback = app.get_one::<String>("Node").unwrap().to_owned();
+    let tasks: Vec<_> = jobs
+        .into_iter()
+        .map(|vmid| {
+            let resource = resources.get(&vmid).cloned();
+            let url = url.clone();
+            let client = client.clone();
+            let token = token.clone();
+            let throttle = throttle.clone();
+            let summary = summary.clone();
+            let command = command.clone();
+            let fallback = fallback.clone();
+            tokio::spawn(async move {
+                let _slot = throttle.acquire().await;
+                let node = resource.as_ref().map_or(fallback, |r| r.node.clone());
+                let mut outcome = summary.start(vmid, &node);
+                outcome.kind = resource.as_ref().map(|r| r.kind.clone());
+                outcome.extra = Some(extra(None));
+                if let Some(reason) = unreachable(resource.as_ref()) {
+                    summary.skipped(outcome, &reason);
+                    return;
+                }
+                match agent::exec(&client, &token, &url, &node, vmid, &command, timeout).await {
+                    Ok(result) => {
+                        if !output::machine() {
+                            print_result(vmid, &result);
+                        }
+                        outcome.extra = Some(extra(Some(&result)));
+                        match result.exitcode {
+                            Some(0) => summary.ok(outcome),
+                            Some(code) => summary.failed(outcome, &format!("exited with {}", code)),
+                            None => summary.failed(outcome, "killed by a signal"),
+                        }
+                    }
+                    Err(e) => {
+                        say!("Error running the command on VMID {}: {}", vmid, e);
+                        summary.failed(outcome, &e.to_string());
+                    }
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await?;
+    }
+    summary.finish()
+}
@@ -0,0 +1,228 @@
+use super::agent::{self, first_ipv4};
+use super::cluster::{get_resources, Resource};
+use super::import::select;
+use super::ipam::config_ip;
+use super::manifest::value_string;
+use super::mgmt::guest_config;
+use clap::ArgMatches;
+use reqwest::header::HeaderMap;
+use reqwest::{Client, ClientBuilder};
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::net::Ipv4Addr;
+
+//A guest the way it ends up in an inventory.
+struct Host {
+    name: String,
+    resource: Resource,
+    address: Option<Ipv4Addr>,
+}
+
+//Tries what the guest reports about itself first, which needs it running and for qemu the guest
+//agent. Falls back to the static ip in ipconfig0 for qemu or net0 for lxc.
+async fn address(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    resource: &Resource,
+) -> Option<Ipv4Addr> {
+    let (node, kind) = (resource.node.as_str(), resource.kind.as_str());
+    if resource.status.as_deref() == Some("running") {
+        if let Ok(interfaces) =
+            agent::interfaces(client, headers, url, node, kind, resource.vmid).await
+        {
+            if let Some(ip) = first_ipv4(&interfaces) {
+                return Some(ip);
+            }
+        }
+    }
+    let config = guest_config(client, headers, url, node, kind, &resource.vmid.to_string())
+        .await
+        .ok()?;
+    let key = if kind == "lxc" { "net0" } else { "ipconfig0" };
+    config_ip(&value_string(config.get(key)))
+}
+
+//Ansible group names can only hold letters, numbers and underscores and can't start with a number,
+//so a tag like 2024-ctf becomes tag_2024_ctf.
+fn group_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    match name.starts_with(|c: char| c.is_ascii_digit()) {
+        true => format!("tag_{}", name),
+        false => name,
+    }
+}
+
+//Group name to the hosts in it, sorted so the output doesn't move around between runs.
+fn groups(hosts: &[Host], group_by: &str) -> BTreeMap<String, Vec<String>> {
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for host in hosts {
+        let names = match group_by {
+            "tag" => host
+                .resource
+                .tag_list()
+                .iter()
+                .map(|t| group_name(t))
+                .collect(),
+            "pool" => host
+                .resource
+                .pool
+                .iter()
+                .map(|p| group_name(&format!("pool_{}", p)))
+                .collect(),
+            _ => Vec::new(),
+        };
+        for name in names {
+            groups.entry(name).or_default().push(host.name.clone());
+        }
+    }
+    groups
+}
+
+fn host_vars(host: &Host, ssh_user: Option<&String>) -> Vec<(String, String)> {
+    let mut vars = Vec::new();
+    if let Some(ip) = host.address {
+        vars.push((String::from("ansible_host"), ip.to_string()));
+    }
+    if let Some(user) = ssh_user {
+        vars.push((String::from("ansible_user"), user.to_owned()));
+    }
+    vars.push((String::from("proxmox_vmid"), host.resource.vmid.to_string()));
+    vars.push((String::from("proxmox_node"), host.resource.node.clone()));
+    vars.push((String::from("proxmox_type"), host.resource.kind.clone()));
+    vars
+}
+
+//Hosts with their variables go at the top, ansible drops them from ungrouped once they show up in
+//a group below.
+fn ini(hosts: &[Host], group_by: &str, ssh_user: Option<&String>) -> String {
+    let mut text = String::from("# Generated by terrabad export\n");
+    for host in hosts {
+        let vars: Vec<String> = host_vars(host, ssh_user)
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        text.push_str(&format!("{} {}\n", host.name, vars.join(" ")));
+    }
+    for (group, members) in groups(hosts, group_by) {
+        text.push_str(&format!("\n[{}]\n{}\n", group, members.join("\n")));
+    }
+    text
+}
+
+fn yaml(
+    hosts: &[Host],
+    group_by: &str,
+    ssh_user: Option<&String>,
+) -> Result<String, Box<dyn Error>> {
+    let mut all_hosts = Mapping::new();
+    for host in hosts {
+        let mut vars = Mapping::new();
+        for (key, value) in host_vars(host, ssh_user) {
+            //The vmid stays a number so playbooks can do math on it.
+            let value = match key == "proxmox_vmid" {
+                true => Value::from(host.resource.vmid),
+                false => Value::from(value),
+            };
+            vars.insert(Value::from(key), value);
+        }
+        all_hosts.insert(Value::from(host.name.clone()), Value::Mapping(vars));
+    }
+    let mut children = Mapping::new();
+    for (group, members) in groups(hosts, group_by) {
+        let mut group_hosts = Mapping::new();
+        for member in members {
+            group_hosts.insert(Value::from(member), Value::Null);
+        }
+        let mut entry = Mapping::new();
+        entry.insert(Value::from("hosts"), Value::Mapping(group_hosts));
+        children.insert(Value::from(group), Value::Mapping(entry));
+    }
+    let mut all = Mapping::new();
+    all.insert(Value::from("hosts"), Value::Mapping(all_hosts));
+    if !children.is_empty() {
+        all.insert(Value::from("children"), Value::Mapping(children));
+    }
+    let mut inventory = Mapping::new();
+    inventory.insert(Value::from("all"), Value::Mapping(all));
+    Ok(serde_yaml::to_string(&inventory)?)
+}
+
+//Hosts without an address keep their Host line, ssh then treats the name as the hostname.
+fn ssh_config(hosts: &[Host], ssh_user: Option<&String>) -> String {
+    let mut text = String::from("# Generated by terrabad export\n");
+    for host in hosts {
+        text.push_str(&format!(
+            "\n# VMID {}, {} on {}\nHost {}\n",
+            host.resource.vmid, host.resource.kind, host.resource.node, host.name
+        ));
+        if let Some(ip) = host.address {
+            text.push_str(&format!("    HostName {}\n", ip));
+        }
+        if let Some(user) = ssh_user {
+            text.push_str(&format!("    User {}\n", user));
+        }
+    }
+    text
+}
+
+//Writes an Ansible inventory or ssh_config for a selection of guests, picked the same way as
+//import. Names come from the guests, addresses from the guest itself or its static ip.
+pub async fn export(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let mut guests = select(&app, get_resources(&client, &token, &url).await?);
+    guests.sort_by_key(|g| g.vmid);
+    let mut hosts = Vec::new();
+    let mut names = HashSet::new();
+    for resource in guests {
+        let mut name = resource
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("vm{}", resource.vmid));
+        //Two guests with the same name would end up as one host.
+        if !names.insert(name.clone()) {
+            name = format!("{}-{}", name, resource.vmid);
+            names.insert(name.clone());
+        }
+        let address = address(&client, &token, &url, &resource).await;
+        if address.is_none() {
+            eprintln!(
+                "No address found for VMID {} ({}), it's exported without one.",
+                resource.vmid, name
+            );
+        }
+        hosts.push(Host {
+            name,
+            resource,
+            address,
+        });
+    }
+    let group_by = app.get_one::<String>("Group_by").unwrap();
+    let ssh_user = app.get_one::<String>("Ssh_user");
+    let text = match app.get_one::<String>("Format").unwrap().as_str() {
+        "yaml" => yaml(&hosts, group_by, ssh_user)?,
+        "ssh_config" => ssh_config(&hosts, ssh_user),
+        _ => ini(&hosts, group_by, ssh_user),
+    };
+    match app.get_one::<String>("File") {
+        Some(path) => {
+            std::fs::write(path, text)?;
+            say!("Wrote {} hosts to {}", hosts.len(), path);
+        }
+        None => print!("{}", text),
+    }
+    Ok(())
+}
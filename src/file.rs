@@ -0,0 +1,233 @@
+use super::agent::{self, MAX_WRITE};
+use super::cloudinit::fill;
+use super::cluster::{get_resources, Resource};
+use super::exec::{unreachable, vmids};
+use super::output;
+use super::summary::Summary;
+use super::throttle::Throttle;
+use clap::ArgMatches;
+use reqwest::ClientBuilder;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::error::Error;
+
+//The fields both add to their records, filled in as far as they got.
+fn record(path: &str, bytes: Option<usize>) -> Map<String, Value> {
+    let mut extra = Map::new();
+    extra.insert(String::from("path"), json!(path));
+    extra.insert(String::from("bytes"), json!(bytes));
+    extra
+}
+
+//Writes the same file to every qemu guest of --source or --min/--max. The path, --content and with
+//--template a --from file can use {name}, {index} and {vmid}, so every guest can get its own flag
+//or config.
+pub async fn file_write(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let path = app.get_one::<String>("Path").unwrap().to_owned();
+    //A file could hold braces of its own, so it's only filled in when asked to.
+    let (content, template) = match (
+        app.get_one::<String>("Content"),
+        app.get_one::<String>("From"),
+    ) {
+        (Some(content), _) => (content.to_owned(), true),
+        (None, Some(from)) => (
+            std::fs::read_to_string(from)
+                .map_err(|e| format!("Unable to read {} as text: {}", from, e))?,
+            app.get_flag("Template"),
+        ),
+        (None, None) => panic!("file_write needs --content or --from"),
+    };
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let throttle = Throttle::from_args(&app, &client, &token, &url).await?;
+    let resources: HashMap<i32, Resource> = get_resources(&client, &token, &url)
+        .await?
+        .into_iter()
+        .map(|r| (r.vmid, r))
+        .collect();
+    let jobs = vmids(&app);
+    let first = jobs.first().copied().unwrap_or_default();
+    let summary = Summary::with_progress("file_write", jobs.len());
+    let fallback = app.get_one::<String>("Node").unwrap().to_owned();
+    let tasks: Vec<_> = jobs
+        .into_iter()
+        .map(|vmid| {
+            let resource = resources.get(&vmid).cloned();
+            let url = url.clone();
+            let client = client.clone();
+            let token = token.clone();
+            let throttle = throttle.clone();
+            let summary = summary.clone();
+            let fallback = fallback.clone();
+            let name = resource
+                .as_ref()
+                .and_then(|r| r.name.clone())
+                .unwrap_or_default();
+            let path = fill(&path, &name, vmid - first, vmid);
+            let content = match template {
+                true => fill(&content, &name, vmid - first, vmid),
+                false => content.clone(),
+            };
+            tokio::spawn(async move {
+                let _slot = throttle.acquire().await;
+                let node = resource.as_ref().map_or(fallback, |r| r.node.clone());
+                let mut outcome = summary.start(vmid, &node);
+                outcome.kind = resource.as_ref().map(|r| r.kind.clone());
+                outcome.extra = Some(record(&path, None));
+                if let Some(reason) = unreachable(resource.as_ref()) {
+                    summary.skipped(outcome, &reason);
+                    return;
+                }
+                if content.len() > MAX_WRITE {
+                    let error = format!(
+                        "{} bytes is more than the {} the guest agent takes",
+                        content.len(),
+                        MAX_WRITE
+                    );
+                    say!("Error writing {} on VMID {}: {}", path, vmid, error);
+                    summary.failed(outcome, &error);
+                    return;
+                }
+                match agent::file_write(&client, &token, &url, &node, vmid, &path, &content).await {
+                    Ok(()) => {
+                        say!("VMID {} wrote {} bytes to {}", vmid, content.len(), path);
+                        outcome.extra = Some(record(&path, Some(content.len())));
+                        summary.ok(outcome);
+                    }
+                    Err(e) => {
+                        say!("Error writing {} on VMID {}: {}", path, vmid, e);
+                        summary.failed(outcome, &e.to_string());
+                    }
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await?;
+    }
+    summary.finish()
+}
+
+//Reads a file from every qemu guest of --source or --min/--max. With --to each copy is saved
+//locally under a path that can use {name}, {index} and {vmid}, otherwise it's printed.
+pub async fn file_read(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let path = app.get_one::<String>("Path").unwrap().to_owned();
+    let to = app.get_one::<String>("To").cloned();
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let throttle = Throttle::from_args(&app, &client, &token, &url).await?;
+    let resources: HashMap<i32, Resource> = get_resources(&client, &token, &url)
+        .await?
+        .into_iter()
+        .map(|r| (r.vmid, r))
+        .collect();
+    let jobs = vmids(&app);
+    //Every guest would write over the last one's copy.
+    if let Some(to) = to.as_ref().filter(|_| jobs.len() > 1) {
+        if !["{vmid}", "{name}", "{index}"]
+            .iter()
+            .any(|p| to.contains(p))
+        {
+            return Err(format!(
+                "--to {} is the same file for all {} guests, put {{vmid}} or {{name}} in it",
+                to,
+                jobs.len()
+            )
+            .into());
+        }
+    }
+    let first = jobs.first().copied().unwrap_or_default();
+    let summary = Summary::with_progress("file_read", jobs.len());
+    let fallback = app.get_one::<String>("Node").unwrap().to_owned();
+    let tasks: Vec<_> = jobs
+        .into_iter()
+        .map(|vmid| {
+            let resource = resources.get(&vmid).cloned();
+            let url = url.clone();
+            let client = client.clone();
+            let token = token.clone();
+            let throttle = throttle.clone();
+            let summary = summary.clone();
+            let fallback = fallback.clone();
+            let name = resource
+                .as_ref()
+                .and_then(|r| r.name.clone())
+                .unwrap_or_default();
+            let path = fill(&path, &name, vmid - first, vmid);
+            let to = to.as_ref().map(|t| fill(t, &name, vmid - first, vmid));
+            tokio::spawn(async move {
+                let _slot = throttle.acquire().await;
+                let node = resource.as_ref().map_or(fallback, |r| r.node.clone());
+                let mut outcome = summary.start(vmid, &node);
+                outcome.kind = resource.as_ref().map(|r| r.kind.clone());
+                let mut extra = record(&path, None);
+                extra.insert(String::from("saved_to"), json!(to));
+                extra.insert(String::from("content"), Value::Null);
+                outcome.extra = Some(extra.clone());
+                if let Some(reason) = unreachable(resource.as_ref()) {
+                    summary.skipped(outcome, &reason);
+                    return;
+                }
+                let file = match agent::file_read(&client, &token, &url, &node, vmid, &path).await {
+                    Ok(file) => file,
+                    Err(e) => {
+                        say!("Error reading {} on VMID {}: {}", path, vmid, e);
+                        summary.failed(outcome, &e.to_string());
+                        return;
+                    }
+                };
+                if file.truncated {
+                    say!("{} on VMID {} was cut off by the guest agent", path, vmid);
+                }
+                extra.insert(String::from("bytes"), json!(file.content.len()));
+                match &to {
+                    Some(local) => {
+                        let parent = std::path::Path::new(local)
+                            .parent()
+                            .filter(|p| !p.as_os_str().is_empty());
+                        let saved = match parent {
+                            Some(parent) => std::fs::create_dir_all(parent),
+                            None => Ok(()),
+                        }
+                        .and_then(|_| std::fs::write(local, &file.content));
+                        if let Err(e) = saved {
+                            say!("Unable to save {} from VMID {}: {}", local, vmid, e);
+                            outcome.extra = Some(extra);
+                            summary.failed(outcome, &e.to_string());
+                            return;
+                        }
+                        say!("VMID {} {} saved to {}", vmid, path, local);
+                    }
+                    None if !output::machine() => {
+                        say!("VMID {} {}:\n{}", vmid, path, file.content.trim_end());
+                    }
+                    None => {
+                        extra.insert(String::from("content"), json!(file.content));
+                    }
+                }
+                outcome.extra = Some(extra);
+                summary.ok(outcome);
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await?;
+    }
+    summary.finish()
+}
@@ -0,0 +1,231 @@
+use super::mgmt::{exit_status, guest_config, guest_type, set_config, UPIDData};
+use super::summary::Summary;
+use clap::ArgMatches;
+use reqwest::header::HeaderMap;
+use reqwest::{Client, ClientBuilder};
+use serde_json::{Map, Value};
+use std::error::Error;
+
+//Holds the hardware changes given on the command line. Anything left as None is left alone on
+//the guest.
+#[derive(Clone, Debug, Default)]
+pub struct Hardware {
+    pub cores: Option<String>,
+    pub sockets: Option<String>,
+    pub memory: Option<String>,
+    pub balloon: Option<String>,
+    pub net: String,
+    pub bridge: Option<String>,
+    pub vlan: Option<String>,
+    pub firewall: Option<String>,
+    pub disk: Option<String>,
+    pub disk_size: Option<String>,
+}
+
+impl Hardware {
+    pub fn from_args(app: &ArgMatches) -> Hardware {
+        Hardware {
+            cores: app.get_one::<String>("Cores").cloned(),
+            sockets: app.get_one::<String>("Sockets").cloned(),
+            memory: app.get_one::<String>("Memory").cloned(),
+            balloon: app.get_one::<String>("Balloon").cloned(),
+            net: app.get_one::<String>("Net").unwrap().to_owned(),
+            bridge: app.get_one::<String>("Bridge").cloned(),
+            vlan: app.get_one::<String>("Vlan").cloned(),
+            firewall: app.get_one::<String>("Firewall").cloned(),
+            disk: app.get_one::<String>("Disk").cloned(),
+            disk_size: app.get_one::<String>("Disk_size").cloned(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cores.is_none()
+            && self.sockets.is_none()
+            && self.memory.is_none()
+            && self.balloon.is_none()
+            && !self.changes_net()
+            && self.disk_size.is_none()
+    }
+
+    fn changes_net(&self) -> bool {
+        self.bridge.is_some() || self.vlan.is_some() || self.firewall.is_some()
+    }
+
+    //Rewrites a net device string, keeping the model, mac and anything else we don't touch. A
+    //missing device is created from scratch, which needs a bridge.
+    pub fn net_string(&self, kind: &str, current: Option<&str>) -> Result<String, Box<dyn Error>> {
+        let mut parts: Vec<String> = match current {
+            Some(c) => c.split(',').map(|part| part.to_owned()).collect(),
+            None => {
+                if self.bridge.is_none() {
+                    return Err(format!("{} doesn't exist and needs a --bridge", self.net).into());
+                }
+                if kind == "qemu" {
+                    vec!["virtio".to_string()]
+                } else {
+                    vec![format!("name=eth{}", self.net.trim_start_matches("net"))]
+                }
+            }
+        };
+        for (key, value) in [
+            ("bridge", &self.bridge),
+            ("tag", &self.vlan),
+            ("firewall", &self.firewall),
+        ] {
+            if let Some(value) = value {
+                let prefix = format!("{}=", key);
+                parts.retain(|part| !part.starts_with(&prefix));
+                parts.push(format!("{}{}", prefix, value));
+            }
+        }
+        Ok(parts.join(","))
+    }
+
+    //Applies the changes to a guest through the config and resize endpoints.
+    pub async fn apply(
+        &self,
+        client: &Client,
+        token: &HeaderMap,
+        url: &str,
+        nodename: &str,
+        kind: &str,
+        vmid: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut json_data = Map::new();
+        if let Some(cores) = &self.cores {
+            json_data.insert("cores".to_string(), Value::String(cores.to_owned()));
+        }
+        if let Some(memory) = &self.memory {
+            json_data.insert("memory".to_string(), Value::String(memory.to_owned()));
+        }
+        //Lxcs don't have sockets or a balloon device.
+        if kind == "qemu" {
+            if let Some(sockets) = &self.sockets {
+                json_data.insert("sockets".to_string(), Value::String(sockets.to_owned()));
+            }
+            if let Some(balloon) = &self.balloon {
+                json_data.insert("balloon".to_string(), Value::String(balloon.to_owned()));
+            }
+        }
+        if self.changes_net() {
+            let config = guest_config(client, token, url, nodename, kind, vmid).await?;
+            let current = config.get(&self.net).and_then(|n| n.as_str());
+            json_data.insert(
+                self.net.clone(),
+                Value::String(self.net_string(kind, current)?),
+            );
+        }
+        if !json_data.is_empty() {
+            set_config(client, token, url, nodename, kind, vmid, &json_data).await?;
+        }
+        if let Some(size) = &self.disk_size {
+            let disk = match &self.disk {
+                Some(d) => d.to_owned(),
+                None if kind == "qemu" => String::from("scsi0"),
+                None => String::from("rootfs"),
+            };
+            resize(client, token, url, nodename, kind, vmid, &disk, size).await?;
+        }
+        say!("Hardware updated on VMID {}", vmid);
+        Ok(())
+    }
+}
+
+//Grows a disk. Size is either absolute (40G) or relative (+10G), disks can't be shrunk.
+#[allow(clippy::too_many_arguments)]
+pub async fn resize(
+    client: &Client,
+    token: &HeaderMap,
+    url: &str,
+    nodename: &str,
+    kind: &str,
+    vmid: &str,
+    disk: &str,
+    size: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut json_data = Map::new();
+    json_data.insert("disk".to_string(), Value::String(disk.to_owned()));
+    json_data.insert("size".to_string(), Value::String(size.to_owned()));
+    let resize_url = format!(
+        "{}/api2/json/nodes/{}/{}/{}/resize",
+        url, nodename, kind, vmid
+    );
+    let response = client
+        .put(resize_url)
+        .headers(token.clone())
+        .json(&json_data)
+        .send()
+        .await?;
+    if response.status() != 200 {
+        return Err(format!(
+            "Unable to resize {} on {}: {}",
+            disk,
+            vmid,
+            response.text().await.unwrap_or_default()
+        )
+        .into());
+    }
+    //Depending on the version and guest type this is either done already or hands back a upid.
+    if let Ok(upid) = serde_json::de::from_str::<UPIDData>(response.text().await?.as_str()) {
+        let status = exit_status(token.clone(), upid, url, nodename).await?;
+        if status != "OK" {
+            return Err(format!("Resizing {} on {} failed: {}", disk, vmid, status).into());
+        }
+    }
+    Ok(())
+}
+
+//Changes the hardware of an existing guest, or every guest in the min/max range.
+pub async fn config_set(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let nodename = app.get_one::<String>("Node").unwrap();
+    let jobs: Vec<String> = match (
+        app.get_one::<i32>("Source"),
+        app.get_one::<i32>("Min"),
+        app.get_one::<i32>("Max"),
+    ) {
+        (Some(src), _, _) => vec![src.to_string()],
+        (None, Some(min), Some(max)) => (*min..*max + 1).map(|vmid| vmid.to_string()).collect(),
+        _ => panic!("This action requires a source VMID or a min/max range"),
+    };
+    let hardware = Hardware::from_args(&app);
+    if hardware.is_empty() {
+        panic!("Nothing to change. Give at least one of the hardware options.");
+    }
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let summary = match jobs.len() {
+        1 => Summary::single("config_set"),
+        n => Summary::with_progress("config_set", n),
+    };
+    for vmid in jobs {
+        let mut outcome = summary.start(vmid.parse::<i32>().unwrap_or_default(), nodename);
+        let kind = match guest_type(&client, &token, &url, nodename, &vmid).await {
+            Ok(k) => k,
+            Err(e) => {
+                say!("Unable to update VMID {}: {}", vmid, e);
+                summary.failed(outcome, &e.to_string());
+                continue;
+            }
+        };
+        outcome.kind = Some(kind.clone());
+        match hardware
+            .apply(&client, &token, &url, nodename, &kind, &vmid)
+            .await
+        {
+            Ok(_) => summary.ok(outcome),
+            Err(e) => {
+                say!("Unable to update VMID {}: {}", vmid, e);
+                summary.failed(outcome, &e.to_string());
+            }
+        }
+    }
+    summary.finish()
+}
@@ -0,0 +1,231 @@
+use super::cluster::{get_resources, Resource};
+use super::manifest::{check_lab, device_value, value_string, Group, Manifest};
+use super::mgmt::guest_config;
+use super::state::StateFile;
+use super::summary::Summary;
+use clap::ArgMatches;
+use reqwest::ClientBuilder;
+use serde_json::{Map, Value};
+use std::error::Error;
+
+//Picks the guests to import from --source (one or more comma separated VMIDs), a min/max range or
+//a tag. Templates are skipped since they're what guests get cloned from, not what gets managed.
+pub fn select(app: &ArgMatches, resources: Vec<Resource>) -> Vec<Resource> {
+    matching(app, resources)
+        .into_iter()
+        .filter(|r| !r.is_template())
+        .collect()
+}
+
+//Everything the selection matches, templates included.
+fn matching(app: &ArgMatches, resources: Vec<Resource>) -> Vec<Resource> {
+    let vmids: Option<Vec<i32>> = match (
+        app.get_many::<i32>("Source"),
+        app.get_one::<i32>("Min"),
+        app.get_one::<i32>("Max"),
+    ) {
+        (Some(src), _, _) => Some(src.copied().collect()),
+        (None, Some(min), Some(max)) => Some((*min..*max + 1).collect()),
+        _ => None,
+    };
+    let tag = app.get_one::<String>("Tag");
+    if vmids.is_none() && tag.is_none() {
+        panic!("This action requires a source VMID, a min/max range or a tag");
+    }
+    resources
+        .into_iter()
+        .filter(|r| vmids.as_ref().is_none_or(|v| v.contains(&r.vmid)))
+        .filter(|r| tag.is_none_or(|t| r.tag_list().contains(t)))
+        .collect()
+}
+
+fn number(config: &Map<String, Value>, key: &str) -> Option<u32> {
+    value_string(config.get(key)).parse::<u32>().ok()
+}
+
+//The disk a group's disk_size applies to. Qemu guests use the first real disk on the usual buses,
+//lxcs always have rootfs.
+fn boot_disk(kind: &str, config: &Map<String, Value>) -> Option<String> {
+    if kind != "qemu" {
+        return Some(String::from("rootfs"));
+    }
+    for bus in ["scsi", "virtio", "sata", "ide"] {
+        for i in 0..31 {
+            let key = format!("{}{}", bus, i);
+            let device = value_string(config.get(&key));
+            if !device.is_empty()
+                && !device.contains("media=cdrom")
+                && !device.contains("cloudinit")
+            {
+                return Some(key);
+            }
+        }
+    }
+    None
+}
+
+//Turns a live guest into a single guest group, so plan and apply treat it exactly as it is now.
+fn to_group(resource: &Resource, config: &Map<String, Value>, lab: &str) -> Group {
+    let net0 = value_string(config.get("net0"));
+    let disk = boot_disk(&resource.kind, config);
+    let disk_size = disk
+        .as_ref()
+        .map(|d| device_value(&value_string(config.get(d)), "size"))
+        .filter(|s| !s.is_empty());
+    let qemu = resource.kind == "qemu";
+    Group {
+        name: resource
+            .name
+            .clone()
+            .unwrap_or_else(|| resource.vmid.to_string()),
+        min: resource.vmid,
+        max: resource.vmid,
+        node: Some(resource.node.clone()),
+        clone_type: String::from("linked"),
+        cores: number(config, "cores").or(Some(1)),
+        sockets: if qemu {
+            number(config, "sockets")
+        } else {
+            None
+        },
+        memory: number(config, "memory"),
+        balloon: if qemu {
+            number(config, "balloon")
+        } else {
+            None
+        },
+        bridge: Some(device_value(&net0, "bridge")).filter(|b| !b.is_empty()),
+        vlan: device_value(&net0, "tag").parse::<u32>().ok(),
+        firewall: match device_value(&net0, "firewall").as_str() {
+            "1" => Some(true),
+            "0" => Some(false),
+            _ => None,
+        },
+        disk: disk.filter(|d| d != "scsi0" && d != "rootfs"),
+        disk_size,
+        tags: resource
+            .tag_list()
+            .into_iter()
+            .filter(|t| t != lab)
+            .collect(),
+        pool: resource.pool.clone(),
+        ..Default::default()
+    }
+}
+
+//Adopts guests that weren't made by terrabad. They're always written to the state file, and with
+//--manifest a manifest describing them is generated too. The manifest's lab is the --tag used to
+//select them, or imported otherwise, and apply will add that tag to any guest missing it.
+//Templates are skipped, but a manifest that would leave out a guest whose config can't be read
+//isn't written at all, since the lab would then claim guests the manifest doesn't describe.
+pub async fn import(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let lab = app
+        .get_one::<String>("Tag")
+        .cloned()
+        .unwrap_or_else(|| String::from("imported"));
+    let manifest_path = app.get_one::<String>("Manifest");
+    if manifest_path.is_some() {
+        check_lab(&lab)?;
+    }
+    let guests = matching(&app, get_resources(&client, &token, &url).await?);
+    if guests.is_empty() {
+        say!("No guests matched.");
+        return Summary::single("import").finish();
+    }
+    if let Some(path) = manifest_path {
+        if std::path::Path::new(path).exists() {
+            return Err(format!("{} already exists, refusing to overwrite it", path).into());
+        }
+    }
+    let state = match manifest_path {
+        Some(_) => StateFile::from_args(&app).for_manifest(&lab),
+        None => StateFile::from_args(&app),
+    };
+    let mut manifest = Manifest {
+        lab: lab.clone(),
+        node: None,
+        guests: Vec::new(),
+    };
+    let summary = Summary::new("import");
+    //Configs are all read before anything is recorded, so a manifest that won't be written
+    //doesn't leave half an import in the state either.
+    let mut imported = Vec::new();
+    let mut skipped = Vec::new();
+    for resource in guests {
+        let mut outcome = summary.start(resource.vmid, &resource.node);
+        outcome.kind = Some(resource.kind.clone());
+        let vmid = resource.vmid.to_string();
+        if resource.is_template() {
+            say!("VMID {} is a template, skipping it", vmid);
+            summary.skipped(outcome, "is a template");
+            continue;
+        }
+        let config = match guest_config(
+            &client,
+            &token,
+            &url,
+            &resource.node,
+            &resource.kind,
+            &vmid,
+        )
+        .await
+        {
+            Ok(c) => c,
+            Err(e) => {
+                say!("Unable to read the config of {}: {}", vmid, e);
+                skipped.push(vmid);
+                summary.failed(outcome, &e.to_string());
+                continue;
+            }
+        };
+        let group = to_group(&resource, &config, &lab);
+        imported.push((resource, group, outcome));
+    }
+    if let (Some(path), false) = (manifest_path, skipped.is_empty()) {
+        for (_, _, outcome) in imported {
+            summary.skipped(outcome, "manifest not written");
+        }
+        //The refusal is the error worth showing, not the count of failed config reads.
+        let _ = summary.finish();
+        return Err(format!(
+            "Not writing {}, it would leave out {} whose config can't be read while claiming lab {}. Narrow the selection or fix those guests first.",
+            path,
+            skipped.join(", "),
+            lab
+        )
+        .into());
+    }
+    for (resource, group, outcome) in imported {
+        //The real name, even an empty one, so state_destroy can compare it with the live guest.
+        state.record(
+            resource.vmid,
+            &resource.node,
+            &resource.kind,
+            None,
+            resource.name.as_deref().unwrap_or_default(),
+        )?;
+        say!("VMID {} imported as {}", resource.vmid, group.name);
+        summary.ok(outcome);
+        manifest.guests.push(group);
+    }
+    if let Some(path) = manifest_path {
+        let text = if path.ends_with(".toml") {
+            toml::to_string_pretty(&manifest)?
+        } else {
+            serde_yaml::to_string(&manifest)?
+        };
+        std::fs::write(path, text)?;
+        say!("Wrote {} guests to {}", manifest.guests.len(), path);
+    }
+    summary.finish()
+}
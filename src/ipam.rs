@@ -0,0 +1,269 @@
+use super::cluster::get_resources;
+use super::mgmt::{guest_config, set_config};
+use reqwest::header::HeaderMap;
+use reqwest::Client;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::net::Ipv4Addr;
+
+//A single static address handed out to a clone.
+#[derive(Clone, Copy, Debug)]
+pub struct Address {
+    pub ip: Ipv4Addr,
+    pub prefix: u8,
+    pub gateway: Option<Ipv4Addr>,
+}
+
+impl Address {
+    //The ip=/gw= pair used by both ipconfig0 and lxc net devices.
+    pub fn config_string(&self) -> String {
+        match self.gateway {
+            Some(gw) => format!("ip={}/{},gw={}", self.ip, self.prefix, gw),
+            None => format!("ip={}/{}", self.ip, self.prefix),
+        }
+    }
+}
+
+//Maps each VMID of a bulk clone to a consecutive address out of a CIDR block. The address part of
+//the block is the first one handed out, so 10.20.0.10/24 gives .10, .11, .12 and so on.
+#[derive(Clone, Debug)]
+pub struct IpPlan {
+    pub addresses: BTreeMap<i32, Address>,
+}
+
+impl IpPlan {
+    pub fn new(
+        cidr: &str,
+        gateway: Option<&String>,
+        vmids: &[i32],
+    ) -> Result<IpPlan, Box<dyn Error>> {
+        let (start, prefix) = parse_cidr(cidr)?;
+        let gateway = match gateway {
+            Some(g) => Some(g.parse::<Ipv4Addr>()?),
+            None => None,
+        };
+        let mask = if prefix == 0 {
+            0
+        } else {
+            u32::MAX << (32 - prefix as u32)
+        };
+        let network = u32::from(start) & mask;
+        let broadcast = network | !mask;
+        let mut addresses = BTreeMap::new();
+        for (i, vmid) in vmids.iter().enumerate() {
+            let ip = u32::from(start) as u64 + i as u64;
+            //Point to point /31 and /32 blocks don't have a network or broadcast address to skip.
+            let last_usable = if prefix >= 31 {
+                broadcast
+            } else {
+                broadcast - 1
+            };
+            if ip > last_usable as u64 || (prefix < 31 && ip == network as u64) {
+                return Err(
+                    format!("{} doesn't have room for {} addresses", cidr, vmids.len()).into(),
+                );
+            }
+            let ip = Ipv4Addr::from(ip as u32);
+            if Some(ip) == gateway {
+                return Err(format!("VMID {} would get the gateway address {}", vmid, ip).into());
+            }
+            addresses.insert(
+                *vmid,
+                Address {
+                    ip,
+                    prefix,
+                    gateway,
+                },
+            );
+        }
+        Ok(IpPlan { addresses })
+    }
+
+    pub fn address(&self, vmid: i32) -> Option<Address> {
+        self.addresses.get(&vmid).copied()
+    }
+
+    //Makes sure none of the planned addresses are already in use by another guest.
+    pub async fn check(
+        &self,
+        client: &Client,
+        token: &HeaderMap,
+        url: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let used = used_addresses(client, token, url).await?;
+        let mut overlaps = Vec::new();
+        for (vmid, address) in &self.addresses {
+            if let Some(owner) = used.get(&address.ip) {
+                //Re-running over the same range shouldn't trip on the addresses we gave out.
+                if owner != vmid {
+                    overlaps.push(format!("{} is already used by VMID {}", address.ip, owner));
+                }
+            }
+        }
+        if !overlaps.is_empty() {
+            return Err(overlaps.join("\n").into());
+        }
+        Ok(())
+    }
+
+    pub fn print(&self) {
+        say!("{:<8} IP", "VMID");
+        for (vmid, address) in &self.addresses {
+            say!("{:<8} {}/{}", vmid, address.ip, address.prefix);
+        }
+    }
+}
+
+fn parse_cidr(cidr: &str) -> Result<(Ipv4Addr, u8), Box<dyn Error>> {
+    let (ip, prefix) = match cidr.split_once('/') {
+        Some((ip, prefix)) => (ip, prefix),
+        None => return Err(format!("{} needs to be in CIDR form, ie 10.20.0.10/24", cidr).into()),
+    };
+    let prefix = prefix.parse::<u8>()?;
+    if prefix > 32 {
+        return Err(format!("{} has an invalid prefix length", cidr).into());
+    }
+    Ok((ip.parse::<Ipv4Addr>()?, prefix))
+}
+
+//Pulls the ip= value out of an ipconfigN or netN string, ignoring dhcp and friends.
+pub fn config_ip(value: &str) -> Option<Ipv4Addr> {
+    value
+        .split(',')
+        .find_map(|part| part.strip_prefix("ip="))
+        .and_then(|ip| ip.split('/').next())
+        .and_then(|ip| ip.parse::<Ipv4Addr>().ok())
+}
+
+//Reads the config of every guest in the cluster and returns the static addresses they use.
+pub async fn used_addresses(
+    client: &Client,
+    token: &HeaderMap,
+    url: &str,
+) -> Result<HashMap<Ipv4Addr, i32>, Box<dyn Error>> {
+    let mut used = HashMap::new();
+    for resource in get_resources(client, token, url).await? {
+        let config = guest_config(
+            client,
+            token,
+            url,
+            &resource.node,
+            &resource.kind,
+            &resource.vmid.to_string(),
+        )
+        .await?;
+        for (key, value) in config {
+            if !(key.starts_with("ipconfig") || key.starts_with("net")) {
+                continue;
+            }
+            if let Some(ip) = value.as_str().and_then(config_ip) {
+                used.insert(ip, resource.vmid);
+            }
+        }
+    }
+    Ok(used)
+}
+
+//Writes the address into ipconfig0 for qemu or into the existing net0 of a lxc.
+pub async fn apply(
+    client: &Client,
+    token: &HeaderMap,
+    url: &str,
+    nodename: &str,
+    kind: &str,
+    vmid: i32,
+    address: &Address,
+) -> Result<(), Box<dyn Error>> {
+    let mut json_data = Map::new();
+    if kind == "qemu" {
+        json_data.insert(
+            "ipconfig0".to_string(),
+            Value::String(address.config_string()),
+        );
+    } else {
+        let config = guest_config(client, token, url, nodename, kind, &vmid.to_string()).await?;
+        let net0 = match config.get("net0").and_then(|n| n.as_str()) {
+            Some(n) => n.to_owned(),
+            None => return Err(format!("VMID {} has no net0 to put an address on", vmid).into()),
+        };
+        //Keep everything about the device except the old ip and gateway.
+        let mut parts: Vec<String> = net0
+            .split(',')
+            .filter(|part| !part.starts_with("ip=") && !part.starts_with("gw="))
+            .map(|part| part.to_owned())
+            .collect();
+        parts.push(address.config_string());
+        json_data.insert("net0".to_string(), Value::String(parts.join(",")));
+    }
+    set_config(
+        client,
+        token,
+        url,
+        nodename,
+        kind,
+        &vmid.to_string(),
+        &json_data,
+    )
+    .await?;
+    say!("VMID {} assigned {}", vmid, address.ip);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> Ipv4Addr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn hands_out_consecutive_addresses() {
+        let gateway = String::from("10.20.0.1");
+        let plan = IpPlan::new("10.20.0.10/24", Some(&gateway), &[200, 201, 202]).unwrap();
+        assert_eq!(plan.address(200).unwrap().ip, ip("10.20.0.10"));
+        assert_eq!(plan.address(202).unwrap().ip, ip("10.20.0.12"));
+        assert!(plan.address(203).is_none());
+        assert_eq!(
+            plan.address(201).unwrap().config_string(),
+            "ip=10.20.0.11/24,gw=10.20.0.1"
+        );
+    }
+
+    #[test]
+    fn refuses_the_broadcast_and_network_addresses() {
+        assert!(IpPlan::new("10.20.0.253/24", None, &[1, 2]).is_ok());
+        assert!(IpPlan::new("10.20.0.253/24", None, &[1, 2, 3]).is_err());
+        assert!(IpPlan::new("10.20.0.0/24", None, &[1]).is_err());
+    }
+
+    #[test]
+    fn point_to_point_blocks_use_every_address() {
+        let plan = IpPlan::new("10.20.0.0/31", None, &[1, 2]).unwrap();
+        assert_eq!(plan.address(2).unwrap().ip, ip("10.20.0.1"));
+        assert!(IpPlan::new("10.20.0.0/31", None, &[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn refuses_the_gateway_and_bad_blocks() {
+        let gateway = String::from("10.20.0.11");
+        assert!(IpPlan::new("10.20.0.10/24", Some(&gateway), &[1, 2]).is_err());
+        assert!(IpPlan::new("10.20.0.10", None, &[1]).is_err());
+        assert!(IpPlan::new("10.20.0.10/33", None, &[1]).is_err());
+    }
+
+    #[test]
+    fn reads_static_addresses_from_config() {
+        assert_eq!(
+            config_ip("ip=10.20.0.5/24,gw=10.20.0.1"),
+            Some(ip("10.20.0.5"))
+        );
+        assert_eq!(
+            config_ip("name=eth0,bridge=vmbr0,ip=10.20.0.6/24"),
+            Some(ip("10.20.0.6"))
+        );
+        assert_eq!(config_ip("ip=dhcp"), None);
+        assert_eq!(config_ip("virtio=AA:BB,bridge=vmbr0"), None);
+    }
+}
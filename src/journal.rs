@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+//Everything needed to pick a bulk_clone back up after it died halfway. Other options like hardware
+//and cloud-init aren't saved, so a resumed run needs to be given the same ones again.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct JournalData {
+    pub action: String,
+    pub source: String,
+    pub node: String,
+    pub full: bool,
+    pub items: Vec<Item>,
+}
+
+//One guest of the run. Status goes pending -> submitted (with the upid) -> done or failed.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Item {
+    pub vmid: i32,
+    pub name: String,
+    pub index: i32,
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Item {
+    pub fn new(vmid: i32, name: &str, index: i32) -> Item {
+        Item {
+            vmid,
+            name: name.to_owned(),
+            index,
+            status: String::from("pending"),
+            kind: None,
+            upid: None,
+            error: None,
+        }
+    }
+}
+
+//The journal is rewritten after every change, so killing terrabad at any point loses at most the
+//upid of a clone that was being submitted right then.
+#[derive(Clone, Debug)]
+pub struct Journal {
+    pub path: String,
+    data: Arc<Mutex<JournalData>>,
+}
+
+impl Journal {
+    //Starts a new run. An unfinished journal is never overwritten since that's the only record of
+    //what the old run was doing.
+    pub fn start(path: &str, data: JournalData) -> Result<Journal, Box<dyn Error>> {
+        Journal::check_free(path)?;
+        let journal = Journal {
+            path: path.to_owned(),
+            data: Arc::new(Mutex::new(data)),
+        };
+        journal.save(&journal.data.lock().unwrap())?;
+        Ok(journal)
+    }
+
+    //Lets a new run fail before its checks instead of after them.
+    pub fn check_free(path: &str) -> Result<(), Box<dyn Error>> {
+        if std::path::Path::new(path).exists() {
+            return Err(format!(
+                "{} holds an unfinished run. Use --resume to finish it, or delete it.",
+                path
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    pub fn resume(path: &str) -> Result<Journal, Box<dyn Error>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(t) => t,
+            Err(e) => return Err(format!("Unable to read the journal {}: {}", path, e).into()),
+        };
+        let data = serde_json::from_str::<JournalData>(&text)?;
+        Ok(Journal {
+            path: path.to_owned(),
+            data: Arc::new(Mutex::new(data)),
+        })
+    }
+
+    pub fn data(&self) -> JournalData {
+        self.data.lock().unwrap().clone()
+    }
+
+    fn save(&self, data: &JournalData) -> Result<(), Box<dyn Error>> {
+        let tmp = format!("{}.tmp", self.path);
+        std::fs::write(&tmp, serde_json::to_string_pretty(data)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    fn update(&self, vmid: i32, change: impl FnOnce(&mut Item)) {
+        let mut data = self.data.lock().unwrap();
+        if let Some(item) = data.items.iter_mut().find(|i| i.vmid == vmid) {
+            change(item);
+        }
+        //A journal that can't be written only hurts resuming, not the run itself.
+        if let Err(e) = self.save(&data) {
+            say!("Unable to write the journal {}: {}", self.path, e);
+        }
+    }
+
+    pub fn submitted(&self, vmid: i32, kind: &str, upid: &str) {
+        self.update(vmid, |item| {
+            item.status = String::from("submitted");
+            item.kind = Some(kind.to_owned());
+            item.upid = Some(upid.to_owned());
+            item.error = None;
+        });
+    }
+
+    pub fn done(&self, vmid: i32, kind: &str) {
+        self.update(vmid, |item| {
+            item.status = String::from("done");
+            item.kind = Some(kind.to_owned());
+            item.error = None;
+        });
+    }
+
+    pub fn failed(&self, vmid: i32, error: &str) {
+        self.update(vmid, |item| {
+            item.status = String::from("failed");
+            item.error = Some(error.to_owned());
+        });
+    }
+
+    //Removes the journal once everything is done, otherwise says how to pick the run back up.
+    pub fn finish(&self) -> Result<(), Box<dyn Error>> {
+        let data = self.data.lock().unwrap();
+        let unfinished = data.items.iter().filter(|i| i.status != "done").count();
+        if unfinished == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            say!(
+                "{} of {} guests didn't finish. Run the same command with --resume to retry them.",
+                unfinished,
+                data.items.len()
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use super::cluster::{get_resources, Resource};
+use super::output::{self, Format, Record};
+use clap::ArgMatches;
+use reqwest::ClientBuilder;
+use serde_json::{json, Value};
+use std::cmp::Ordering;
+use std::error::Error;
+
+//Every column list knows about, in the order they're shown by default.
+pub const COLUMNS: [&str; 13] = [
+    "vmid", "name", "type", "node", "status", "tags", "pool", "cpu", "maxcpu", "mem", "maxmem",
+    "uptime", "template",
+];
+
+//The raw value of a column. This is what json and csv get, the table makes it readable.
+fn value(resource: &Resource, column: &str) -> Value {
+    match column {
+        "vmid" => json!(resource.vmid),
+        "name" => json!(resource.name),
+        "type" => json!(resource.kind),
+        "node" => json!(resource.node),
+        "status" => json!(resource.status),
+        "tags" => json!(resource.tag_list()),
+        "pool" => json!(resource.pool),
+        "cpu" => json!(resource.cpu),
+        "maxcpu" => json!(resource.maxcpu),
+        "mem" => json!(resource.mem),
+        "maxmem" => json!(resource.maxmem),
+        "uptime" => json!(resource.uptime),
+        "template" => json!(resource.is_template()),
+        _ => Value::Null,
+    }
+}
+
+fn cell(column: &str, value: &Value) -> String {
+    match (column, value) {
+        (_, Value::Null) => String::from("-"),
+        ("cpu", Value::Number(n)) => format!("{:.1}%", n.as_f64().unwrap_or_default() * 100.0),
+        ("mem" | "maxmem", Value::Number(n)) => bytes(n.as_u64().unwrap_or_default()),
+        ("uptime", Value::Number(n)) => duration(n.as_u64().unwrap_or_default()),
+        ("template", Value::Bool(b)) => String::from(if *b { "yes" } else { "no" }),
+        (_, Value::Number(n)) => n.as_f64().map_or(n.to_string(), |f| f.to_string()),
+        (_, Value::String(s)) => s.to_owned(),
+        (_, Value::Array(items)) if items.is_empty() => String::from("-"),
+        (_, Value::Array(items)) => items
+            .iter()
+            .filter_map(|item| item.as_str())
+            .collect::<Vec<_>>()
+            .join(";"),
+        (_, other) => other.to_string(),
+    }
+}
+
+fn bytes(bytes: u64) -> String {
+    let mib = bytes as f64 / 1048576.0;
+    match bytes {
+        0..=1048575 => format!("{}K", bytes / 1024),
+        _ if mib < 1024.0 => format!("{:.0}M", mib),
+        _ => format!("{:.1}G", mib / 1024.0),
+    }
+}
+
+fn duration(secs: u64) -> String {
+    match secs {
+        0 => String::from("-"),
+        1..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        3600..=86399 => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d{:02}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+//Numbers sort as numbers, everything else as text. Empty values go last either way round.
+fn compare(a: &Value, b: &Value, reverse: bool) -> Ordering {
+    let order = match (a, b) {
+        (Value::Null, Value::Null) => return Ordering::Equal,
+        (Value::Null, _) => return Ordering::Greater,
+        (_, Value::Null) => return Ordering::Less,
+        (Value::Number(a), Value::Number(b)) => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (a, b) => cell("", a).cmp(&cell("", b)),
+    };
+    match reverse {
+        true => order.reverse(),
+        false => order,
+    }
+}
+
+fn wanted(app: &ArgMatches, resource: &Resource) -> Result<bool, Box<dyn Error>> {
+    if let Some(min) = app.get_one::<i32>("Min") {
+        if resource.vmid < *min {
+            return Ok(false);
+        }
+    }
+    if let Some(max) = app.get_one::<i32>("Max") {
+        if resource.vmid > *max {
+            return Ok(false);
+        }
+    }
+    if let Some(tag) = app.get_one::<String>("Tag") {
+        if !resource.tag_list().contains(tag) {
+            return Ok(false);
+        }
+    }
+    if let Some(node) = app.get_one::<String>("Filter_node") {
+        if &resource.node != node {
+            return Ok(false);
+        }
+    }
+    if let Some(status) = app.get_one::<String>("Status") {
+        if resource.status.as_ref() != Some(status) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+//Lists every guest in the cluster, not just the ones on --node.
+pub async fn list(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let columns: Vec<String> = match app.get_one::<String>("Columns") {
+        Some(c) => c.split(',').map(|c| c.trim().to_lowercase()).collect(),
+        None => COLUMNS.iter().map(|c| c.to_string()).collect(),
+    };
+    if let Some(unknown) = columns.iter().find(|c| !COLUMNS.contains(&c.as_str())) {
+        return Err(format!(
+            "Unknown column {}, pick from {}",
+            unknown,
+            COLUMNS.join(",")
+        )
+        .into());
+    }
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let mut guests = Vec::new();
+    for resource in get_resources(&client, &token, &url).await? {
+        if wanted(&app, &resource)? {
+            guests.push(resource);
+        }
+    }
+    let sort = app.get_one::<String>("Sort").unwrap();
+    guests.sort_by_key(|g| g.vmid);
+    let reverse = app.get_flag("Reverse");
+    //Only the column is reversed, guests that tie stay in VMID order.
+    guests.sort_by(|a, b| compare(&value(a, sort), &value(b, sort), reverse));
+    if output::format() != Format::Text {
+        let records: Vec<Record> = guests
+            .iter()
+            .map(|g| Record(columns.iter().map(|c| (c.clone(), value(g, c))).collect()))
+            .collect();
+        return output::records(&records);
+    }
+    let rows: Vec<Vec<String>> = guests
+        .iter()
+        .map(|g| columns.iter().map(|c| cell(c, &value(g, c))).collect())
+        .collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, c)| rows.iter().map(|r| r[i].len()).fold(c.len(), usize::max))
+        .collect();
+    let line = |cells: Vec<String>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        say!("{}", padded.join("  ").trim_end());
+    };
+    line(columns.iter().map(|c| c.to_uppercase()).collect());
+    for row in rows {
+        line(row);
+    }
+    say!("{} guests", guests.len());
+    Ok(())
+}
@@ -1,110 +1,129 @@
+#[macro_use]
+mod output;
+mod agent;
 mod auth;
+mod cli;
+mod cloudinit;
+mod cluster;
+mod drift;
+mod exec;
+mod export;
+mod file;
+mod hardware;
+mod import;
+mod ipam;
+mod journal;
+mod list;
+mod manifest;
 mod mgmt;
-use clap::{Arg, Command};
+mod migrate;
+mod preflight;
+mod progress;
+mod retry;
+mod show;
+mod state;
+mod summary;
+mod template;
+mod throttle;
+use clap::ArgMatches;
 use std::error::Error;
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let app =
-        Command::new("terrabad")
-            .author("numonce")
-            .about("A tool for managing proxmox functions written in pure rust.")
-            .version("1.0.0")
-            .arg(
-                Arg::new("Url")
-                    .long("url")
-                    .short('U')
-                    .help("url of the Proxmox host")
-                    .required(true),
-            )
-            .arg(
-                Arg::new("Username")
-                    .long("user")
-                    .short('u')
-                    .help("Username for proxmox auth")
-                    .required(true),
-            )
-            .arg(
-                Arg::new("Password")
-                    .long("password")
-                    .short('p')
-                    .help("Password for proxmox auth")
-                    .required(true),
-            )
-            .arg(
-                Arg::new("Action")
-                    .long("action")
-                    .short('a')
-                    .help("clone, etc...")
-                    .required(true)
-                    .value_parser([
-                        "clone",
-                        "destroy",
-                        "bulk_clone",
-                        "bulk_destroy",
-                        "bulk_start",
-                        "bulk_stop",
-                    ]),
-            )
-            .arg(
-                Arg::new("Name").long("name").short('n').help(
-                    "Desired name of the created VM. For bulk actions this will add a number.",
-                ),
-            )
-            .arg(
-                Arg::new("Node")
-                    .long("node")
-                    .short('N')
-                    .required(true)
-                    .help("Name of the node"),
-            )
-            .arg(
-                Arg::new("Source")
-                    .long("source")
-                    .short('s')
-                    .help("Source template VMID for action."),
-            )
-            .arg(Arg::new("Destination").long("destination").short('d').help(
-                "Destination template VMID for action. This is only needed for single actions.",
-            ))
-            .arg(
-                Arg::new("Min")
-                    .long("min")
-                    .short('m')
-                    .requires("Action")
-                    .help("First VMID for range. Needed for bulk actions.")
-                    .required_if_eq_any([("Action", "bulk_clone")]),
-            )
-            .arg(
-                Arg::new("Max")
-                    .long("max")
-                    .short('M')
-                    .requires("Action")
-                    .help("Last VMID for range. Needed for bulk actions.")
-                    .required_if_eq_any([("Action", "bulk_clone")]),
-            )
-            .arg(
-                Arg::new("Clone_type")
-                    .long("clone_type")
-                    .short('T')
-                    .help("Type of clone. Can either be linked or full.")
-                    .default_value("linked")
-                    .value_parser(["linked", "full"]),
-            )
-            .arg(
-                Arg::new("Threads")
-                    .long("threads")
-                    .short('t')
-                    .help("Number of workers.")
-                    .default_value("1"),
-            )
-            .get_matches();
-    match app.get_one::<String>("Action").unwrap().as_str() {
+async fn main() {
+    //Exit codes: 0 when everything worked, 2 when a bulk action only partly failed, 1 otherwise.
+    if let Err(e) = run().await {
+        progress::clear();
+        eprintln!("Error: {}", e);
+        let code = match e.downcast_ref::<summary::PartialFailure>() {
+            Some(p) if p.failed < p.total => 2,
+            _ => 1,
+        };
+        std::process::exit(code);
+    }
+}
+
+async fn run() -> Result<(), Box<dyn Error>> {
+    let app = cli::command().get_matches();
+    match app.subcommand() {
+        Some((name, sub)) => subcommand(name, sub.clone()).await,
+        None => match app.get_one::<String>("Action") {
+            Some(action) => {
+                eprintln!(
+                    "--action is deprecated, use `terrabad ... {}` instead.",
+                    cli::subcommand_for(action)
+                );
+                action_alias(action.to_owned(), app).await
+            }
+            None => cli::command()
+                .error(
+                    clap::error::ErrorKind::MissingSubcommand,
+                    "a subcommand is required, see --help",
+                )
+                .exit(),
+        },
+    }
+}
+
+//Subcommands that handle both one guest and a range pick the bulk version when there's no single
+//VMID given.
+async fn subcommand(name: &str, app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    output::init(&app);
+    cli::require_connection(name, &app);
+    let single = |id: &str| app.get_one::<i32>(id).is_some();
+    match name {
+        "clone" if single("Destination") => mgmt::create_clone(app).await?,
+        "clone" => mgmt::bulk_clone(app).await?,
+        "destroy" if single("Source") => mgmt::destroy_vm(app).await?,
+        "destroy" => mgmt::bulk_destroy(app).await?,
+        "start" => mgmt::bulk_start(app).await?,
+        "stop" => mgmt::bulk_stop(app).await?,
+        "migrate" if single("Source") => migrate::migrate(app).await?,
+        "migrate" => migrate::bulk_migrate(app).await?,
+        "evacuate" => migrate::evacuate(app).await?,
+        "template" => template::template(app).await?,
+        "template_build" => template::template_build(app).await?,
+        "config_set" => hardware::config_set(app).await?,
+        "plan" => manifest::plan(app).await?,
+        "apply" => manifest::apply(app).await?,
+        "state_list" => state::state_list(app)?,
+        "state_refresh" => state::state_refresh(app).await?,
+        "state_destroy" => state::state_destroy(app).await?,
+        "drift" => drift::drift(app).await?,
+        "import" => import::import(app).await?,
+        "list" => list::list(app).await?,
+        "show" => show::show(app).await?,
+        "export" => export::export(app).await?,
+        "exec" => exec::exec(app).await?,
+        "file_write" => file::file_write(app).await?,
+        "file_read" => file::file_read(app).await?,
+        _ => panic!("Something incredibly bad occured if you can see this."),
+    }
+    Ok(())
+}
+
+//The old way of picking what to do, kept so existing scripts don't break.
+async fn action_alias(action: String, app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    output::init(&app);
+    cli::require_connection(cli::subcommand_for(&action), &app);
+    match action.as_str() {
         "clone" => mgmt::create_clone(app).await?,
         "destroy" => mgmt::destroy_vm(app).await?,
         "bulk_clone" => mgmt::bulk_clone(app).await?,
         "bulk_destroy" => mgmt::bulk_destroy(app).await?,
         "bulk_start" => mgmt::bulk_start(app).await?,
         "bulk_stop" => mgmt::bulk_stop(app).await?,
+        "migrate" => migrate::migrate(app).await?,
+        "bulk_migrate" => migrate::bulk_migrate(app).await?,
+        "evacuate" => migrate::evacuate(app).await?,
+        "template" => template::template(app).await?,
+        "template_build" => template::template_build(app).await?,
+        "config_set" => hardware::config_set(app).await?,
+        "plan" => manifest::plan(app).await?,
+        "apply" => manifest::apply(app).await?,
+        "state_list" => state::state_list(app)?,
+        "state_refresh" => state::state_refresh(app).await?,
+        "state_destroy" => state::state_destroy(app).await?,
+        "drift" => drift::drift(app).await?,
+        "import" => import::import(app).await?,
         _ => panic!("Something incredibly bad occured if you can see this."),
     }
     Ok(())
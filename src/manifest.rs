@@ -0,0 +1,999 @@
+use super::cloudinit::{self, decode_sshkeys, CloudInit};
+use super::cluster::{get_resources, Resource};
+use super::hardware::Hardware;
+use super::mgmt::{
+    clone_guest, destroy_guest, guest_config, guest_status, lxc_clone_turn, post_clone, set_config,
+    stop_guest,
+};
+use super::output;
+use super::retry::Retry;
+use super::state::{State, StateFile};
+use super::summary::Summary;
+use super::throttle::Throttle;
+use clap::ArgMatches;
+use reqwest::header::HeaderMap;
+use reqwest::{Client, ClientBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::error::Error;
+
+//A lab described in a yaml or toml file. Every guest it creates is tagged with the lab name, which
+//is how plan finds guests that used to be in the manifest and should now be destroyed.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Manifest {
+    pub lab: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+    #[serde(default)]
+    pub guests: Vec<Group>,
+}
+
+//A range of guests cloned from the same template with the same settings. Like bulk_clone each
+//guest is named name + (vmid - min), unless the range is a single guest which just gets name.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Group {
+    pub name: String,
+    pub min: i32,
+    pub max: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+    #[serde(default = "default_clone_type")]
+    pub clone_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cores: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sockets: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balloon: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bridge: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vlan: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firewall: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk_size: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cloudinit: Option<CloudInit>,
+}
+
+fn default_clone_type() -> String {
+    String::from("linked")
+}
+
+//One guest the manifest wants to exist.
+#[derive(Debug, Clone)]
+pub struct Desired {
+    pub vmid: i32,
+    pub name: String,
+    pub node: String,
+    pub index: i32,
+    pub group: Group,
+    //Where the template lives, filled in by compute_plan for guests that need creating.
+    pub template_node: Option<String>,
+}
+
+//A single value that differs between the manifest and the cluster.
+#[derive(Serialize, Debug, Clone)]
+pub struct Diff {
+    pub key: String,
+    pub current: String,
+    pub wanted: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum Change {
+    Create(Desired),
+    Modify(Desired, String, Vec<Diff>),
+    Destroy(Resource),
+}
+
+//The lab ends up as a tag on every guest, so it has to be something proxmox takes as one.
+pub fn check_lab(lab: &str) -> Result<(), Box<dyn Error>> {
+    if lab.is_empty()
+        || !lab
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "lab {:?} needs to be lowercase letters, digits, - or _ since it's used as a tag",
+            lab
+        )
+        .into());
+    }
+    Ok(())
+}
+
+impl Manifest {
+    //Picks the format from the file extension, anything that isn't .toml is read as yaml.
+    pub fn load(path: &str) -> Result<Manifest, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        let mut manifest: Manifest = if path.ends_with(".toml") {
+            toml::from_str(&text)?
+        } else {
+            serde_yaml::from_str(&text)?
+        };
+        check_lab(&manifest.lab)?;
+        let mut seen = HashSet::new();
+        for group in manifest.guests.iter_mut() {
+            if group.min > group.max {
+                return Err(format!("{} has a min bigger than its max", group.name).into());
+            }
+            for vmid in group.min..group.max + 1 {
+                if !seen.insert(vmid) {
+                    return Err(format!("VMID {} is in more than one group", vmid).into());
+                }
+            }
+            if let Some(cloudinit) = group.cloudinit.as_mut() {
+                cloudinit.read_sshkeys()?;
+            }
+        }
+        Ok(manifest)
+    }
+
+    pub fn desired(&self, default_node: &str) -> Vec<Desired> {
+        let mut desired = Vec::new();
+        for group in &self.guests {
+            let node = group
+                .node
+                .clone()
+                .or_else(|| self.node.clone())
+                .unwrap_or_else(|| default_node.to_owned());
+            for vmid in group.min..group.max + 1 {
+                desired.push(Desired {
+                    vmid,
+                    name: group.guest_name(vmid),
+                    node: node.clone(),
+                    index: vmid - group.min,
+                    group: group.clone(),
+                    template_node: None,
+                });
+            }
+        }
+        desired
+    }
+}
+
+impl Group {
+    pub fn guest_name(&self, vmid: i32) -> String {
+        if self.min == self.max {
+            self.name.clone()
+        } else {
+            format!("{}{}", self.name, vmid - self.min)
+        }
+    }
+
+    pub fn hardware(&self) -> Hardware {
+        Hardware {
+            cores: self.cores.map(|c| c.to_string()),
+            sockets: self.sockets.map(|s| s.to_string()),
+            memory: self.memory.map(|m| m.to_string()),
+            balloon: self.balloon.map(|b| b.to_string()),
+            net: String::from("net0"),
+            bridge: self.bridge.clone(),
+            vlan: self.vlan.map(|v| v.to_string()),
+            firewall: self
+                .firewall
+                .map(|f| String::from(if f { "1" } else { "0" })),
+            disk: self.disk.clone(),
+            disk_size: self.disk_size.clone(),
+        }
+    }
+
+    //The tags the guests should carry, which always includes the lab tag.
+    pub fn tag_list(&self, lab: &str) -> Vec<String> {
+        let mut tags = vec![lab.to_owned()];
+        for tag in &self.tags {
+            if !tags.contains(tag) {
+                tags.push(tag.to_owned());
+            }
+        }
+        tags
+    }
+}
+
+//Config values come back as either strings or numbers depending on the key.
+pub fn value_string(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(s)) => s.to_owned(),
+        Some(Value::Null) | None => String::new(),
+        Some(v) => v.to_string(),
+    }
+}
+
+//Pulls key=value out of a device string like virtio=AA:BB,bridge=vmbr0,tag=20.
+pub fn device_value(device: &str, key: &str) -> String {
+    let prefix = format!("{}=", key);
+    device
+        .split(',')
+        .find_map(|part| part.strip_prefix(prefix.as_str()))
+        .unwrap_or_default()
+        .to_owned()
+}
+
+//Turns 32G, 512M or 1T into bytes so sizes written differently still compare equal.
+pub fn size_in_bytes(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let (number, unit) = match size.char_indices().find(|(_, c)| c.is_ascii_alphabetic()) {
+        Some((i, _)) => (&size[..i], &size[i..]),
+        None => (size, ""),
+    };
+    let number = number.parse::<f64>().ok()?;
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return None,
+    };
+    Some((number * multiplier as f64) as u64)
+}
+
+//Compares what a guest should look like to its live config and returns every difference. Only
+//settings the manifest actually sets are compared.
+pub fn diff_config(
+    desired: &Desired,
+    lab: &str,
+    kind: &str,
+    config: &Map<String, Value>,
+) -> Vec<Diff> {
+    let group = &desired.group;
+    let mut diffs = Vec::new();
+    let mut compare = |key: &str, current: String, wanted: String| {
+        if current != wanted {
+            diffs.push(Diff {
+                key: key.to_owned(),
+                current,
+                wanted,
+            });
+        }
+    };
+    let name_key = if kind == "qemu" { "name" } else { "hostname" };
+    compare(
+        name_key,
+        value_string(config.get(name_key)),
+        desired.name.clone(),
+    );
+    if let Some(cores) = group.cores {
+        //Proxmox leaves cores out of the config when it's the default of 1.
+        let current = value_string(config.get("cores"));
+        let current = if current.is_empty() {
+            String::from("1")
+        } else {
+            current
+        };
+        compare("cores", current, cores.to_string());
+    }
+    if let Some(memory) = group.memory {
+        compare(
+            "memory",
+            value_string(config.get("memory")),
+            memory.to_string(),
+        );
+    }
+    if kind == "qemu" {
+        if let Some(sockets) = group.sockets {
+            let current = value_string(config.get("sockets"));
+            let current = if current.is_empty() {
+                String::from("1")
+            } else {
+                current
+            };
+            compare("sockets", current, sockets.to_string());
+        }
+        if let Some(balloon) = group.balloon {
+            compare(
+                "balloon",
+                value_string(config.get("balloon")),
+                balloon.to_string(),
+            );
+        }
+    }
+    let net0 = value_string(config.get("net0"));
+    if let Some(bridge) = &group.bridge {
+        compare("net0.bridge", device_value(&net0, "bridge"), bridge.clone());
+    }
+    if let Some(vlan) = group.vlan {
+        compare("net0.tag", device_value(&net0, "tag"), vlan.to_string());
+    }
+    if let Some(firewall) = group.firewall {
+        let current = device_value(&net0, "firewall");
+        let current = if current.is_empty() {
+            String::from("0")
+        } else {
+            current
+        };
+        compare(
+            "net0.firewall",
+            current,
+            String::from(if firewall { "1" } else { "0" }),
+        );
+    }
+    //Relative sizes like +10G only make sense once, so those aren't compared.
+    if let Some(size) = group.disk_size.as_ref().filter(|s| !s.starts_with('+')) {
+        let disk = match &group.disk {
+            Some(d) => d.to_owned(),
+            None if kind == "qemu" => String::from("scsi0"),
+            None => String::from("rootfs"),
+        };
+        let current = device_value(&value_string(config.get(&disk)), "size");
+        if size_in_bytes(&current) != size_in_bytes(size) {
+            compare(&format!("{}.size", disk), current, size.to_owned());
+        }
+    }
+    //Cloud-init is compared the way it would be rendered for this guest. Sshkeys are stored url
+    //encoded, so both sides are decoded first.
+    if let Some(cloudinit) = &group.cloudinit {
+        for (key, wanted) in cloudinit.render(kind, &desired.name, desired.index, desired.vmid) {
+            let mut current = value_string(config.get(&key));
+            let mut wanted = value_string(Some(&wanted));
+            if key == "sshkeys" {
+                current = decode_sshkeys(&current).trim().to_owned();
+                wanted = decode_sshkeys(&wanted).trim().to_owned();
+            }
+            compare(&key, current, wanted);
+        }
+    }
+    let mut current_tags: Vec<String> = value_string(config.get("tags"))
+        .split([';', ',', ' '])
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| tag.to_owned())
+        .collect();
+    let mut wanted_tags = group.tag_list(lab);
+    current_tags.sort();
+    wanted_tags.sort();
+    compare("tags", current_tags.join(";"), wanted_tags.join(";"));
+    diffs
+}
+
+//Works out what needs to be created, modified and destroyed to make the cluster match the
+//manifest. Guests are only touched when they carry the lab tag or are in the state under this
+//manifest's run, and only destroyed when the state has them unless destroy_unmanaged is set.
+pub async fn compute_plan(
+    manifest: &Manifest,
+    client: &Client,
+    token: &HeaderMap,
+    url: &str,
+    default_node: &str,
+    state: &State,
+    destroy_unmanaged: bool,
+) -> Result<Vec<Change>, Box<dyn Error>> {
+    let resources = get_resources(client, token, url).await?;
+    let desired = manifest.desired(default_node);
+    let wanted: HashSet<i32> = desired.iter().map(|d| d.vmid).collect();
+    let run = format!("manifest:{}", manifest.lab);
+    let managed: HashSet<i32> = state
+        .resources
+        .iter()
+        .filter(|e| e.run == run)
+        .map(|e| e.vmid)
+        .collect();
+    let mut changes = Vec::new();
+    let mut conflicts = Vec::new();
+    for guest in desired {
+        let resource = match resources.iter().find(|r| r.vmid == guest.vmid) {
+            Some(r) => r,
+            None => {
+                //Clones have to start on the template's node and are moved over with target.
+                let mut guest = guest;
+                guest.template_node = guest
+                    .group
+                    .template
+                    .and_then(|t| resources.iter().find(|r| r.vmid == t))
+                    .map(|r| r.node.clone());
+                changes.push(Change::Create(guest));
+                continue;
+            }
+        };
+        //Someone else's guest sitting on a VMID the manifest wants isn't taken over.
+        if resource.is_template()
+            || (!resource.tag_list().contains(&manifest.lab) && !managed.contains(&resource.vmid))
+        {
+            conflicts.push(format!(
+                "{} ({})",
+                resource.vmid,
+                resource.name.clone().unwrap_or_default()
+            ));
+            continue;
+        }
+        let config = guest_config(
+            client,
+            token,
+            url,
+            &resource.node,
+            &resource.kind,
+            &guest.vmid.to_string(),
+        )
+        .await?;
+        let mut diffs = diff_config(&guest, &manifest.lab, &resource.kind, &config);
+        if let Some(pool) = &guest.group.pool {
+            if resource.pool.as_ref() != Some(pool) {
+                diffs.push(Diff {
+                    key: String::from("pool"),
+                    current: resource.pool.clone().unwrap_or_default(),
+                    wanted: pool.to_owned(),
+                });
+            }
+        }
+        if !diffs.is_empty() {
+            //Changes are made wherever the guest lives now, not where the manifest would put it.
+            let mut guest = guest;
+            guest.node = resource.node.clone();
+            changes.push(Change::Modify(guest, resource.kind.clone(), diffs));
+        }
+    }
+    if !conflicts.is_empty() {
+        return Err(format!(
+            "VMIDs already in use by guests outside lab {}: {}",
+            manifest.lab,
+            conflicts.join(", ")
+        )
+        .into());
+    }
+    for resource in resources {
+        if wanted.contains(&resource.vmid)
+            || resource.is_template()
+            || !resource.tag_list().contains(&manifest.lab)
+        {
+            continue;
+        }
+        if managed.contains(&resource.vmid) || destroy_unmanaged {
+            changes.push(Change::Destroy(resource));
+        } else {
+            say!(
+                "VMID {} carries the {} tag but wasn't created by this manifest, leaving it alone. Use --destroy_unmanaged to destroy it too.",
+                resource.vmid,
+                manifest.lab
+            );
+        }
+    }
+    Ok(changes)
+}
+
+pub fn print_plan(changes: &[Change]) {
+    let (mut create, mut modify, mut destroy) = (0, 0, 0);
+    for change in changes {
+        match change {
+            Change::Create(guest) => {
+                create += 1;
+                match guest.group.template {
+                    Some(template) => say!(
+                        "+ {} {} (clone of {} on {})",
+                        guest.vmid,
+                        guest.name,
+                        template,
+                        guest.node
+                    ),
+                    None => say!(
+                        "+ {} {} (no template set, can't be created)",
+                        guest.vmid,
+                        guest.name
+                    ),
+                }
+            }
+            Change::Modify(guest, _, diffs) => {
+                modify += 1;
+                say!("~ {} {}", guest.vmid, guest.name);
+                for diff in diffs {
+                    say!("    {}: {:?} -> {:?}", diff.key, diff.current, diff.wanted);
+                }
+            }
+            Change::Destroy(resource) => {
+                destroy += 1;
+                say!(
+                    "- {} {} on {}",
+                    resource.vmid,
+                    resource.name.clone().unwrap_or_default(),
+                    resource.node
+                );
+            }
+        }
+    }
+    say!(
+        "Plan: {} to create, {} to modify, {} to destroy.",
+        create,
+        modify,
+        destroy
+    );
+}
+
+//Adds a guest to a pool. Guests can only be in one pool so proxmox moves it if it's in another.
+async fn add_to_pool(
+    client: &Client,
+    token: &HeaderMap,
+    url: &str,
+    pool: &str,
+    vmid: i32,
+) -> Result<(), Box<dyn Error>> {
+    let pool_url = format!("{}/api2/json/pools/{}", url, pool);
+    let mut json_data = Map::new();
+    json_data.insert("vms".to_string(), Value::String(vmid.to_string()));
+    json_data.insert("allow-move".to_string(), Value::Bool(true));
+    let response = client
+        .put(pool_url)
+        .headers(token.clone())
+        .json(&json_data)
+        .send()
+        .await?;
+    if response.status() != 200 {
+        return Err(response.text().await.unwrap_or_default().into());
+    }
+    Ok(())
+}
+
+//Sets the name and tags the manifest wants on a guest.
+async fn set_identity(
+    client: &Client,
+    token: &HeaderMap,
+    url: &str,
+    lab: &str,
+    kind: &str,
+    guest: &Desired,
+) -> Result<(), Box<dyn Error>> {
+    let mut json_data = Map::new();
+    let name_key = if kind == "qemu" { "name" } else { "hostname" };
+    json_data.insert(name_key.to_string(), Value::String(guest.name.clone()));
+    json_data.insert(
+        "tags".to_string(),
+        Value::String(guest.group.tag_list(lab).join(";")),
+    );
+    set_config(
+        client,
+        token,
+        url,
+        &guest.node,
+        kind,
+        &guest.vmid.to_string(),
+        &json_data,
+    )
+    .await
+}
+
+//Carries out a single change using the same clone, config and destroy calls as the imperative
+//actions. Every call is retried on its own, so a lock while setting the name doesn't clone the
+//guest a second time.
+pub async fn apply_change(
+    change: &Change,
+    client: &Client,
+    token: &HeaderMap,
+    url: &str,
+    lab: &str,
+    state: &StateFile,
+    retry: Retry,
+) -> Result<(), Box<dyn Error>> {
+    match change {
+        Change::Create(guest) => {
+            let template = match guest.group.template {
+                Some(t) => t.to_string(),
+                None => return Err(format!("{} has no template to clone from", guest.name).into()),
+            };
+            let source_node = guest.template_node.as_deref().unwrap_or(&guest.node);
+            let target = Some(guest.node.as_str()).filter(|n| *n != source_node);
+            let kind = retry
+                .run(guest.vmid, || async {
+                    clone_guest(
+                        client,
+                        token,
+                        url,
+                        source_node,
+                        target,
+                        &template,
+                        guest.vmid,
+                        &guest.name,
+                        guest.group.clone_type == "full",
+                        guest.group.pool.as_deref(),
+                    )
+                    .await
+                    .map_err(|e| e.to_string())
+                })
+                .await?;
+            state.record(guest.vmid, &guest.node, &kind, Some(&template), &guest.name)?;
+            retry
+                .run(guest.vmid, || async {
+                    set_identity(client, token, url, lab, &kind, guest)
+                        .await
+                        .map_err(|e| e.to_string())
+                })
+                .await?;
+            post_clone(
+                client,
+                token,
+                url,
+                &guest.node,
+                &kind,
+                guest.vmid,
+                &guest.name,
+                guest.index,
+                &guest.group.hardware(),
+                &guest.group.cloudinit.clone().unwrap_or_default(),
+                None,
+            )
+            .await;
+            say!("VMID {} created", guest.vmid);
+        }
+        Change::Modify(guest, kind, diffs) => {
+            let keys: Vec<&str> = diffs.iter().map(|d| d.key.as_str()).collect();
+            //Only the settings that drifted are sent, mostly so disks aren't resized twice.
+            let mut hardware = guest.group.hardware();
+            if !keys.iter().any(|k| k.ends_with(".size")) {
+                hardware.disk_size = None;
+            }
+            if !keys.iter().any(|k| k.starts_with("net0.")) {
+                hardware.bridge = None;
+                hardware.vlan = None;
+                hardware.firewall = None;
+            }
+            if !hardware.is_empty() {
+                retry
+                    .run(guest.vmid, || async {
+                        hardware
+                            .apply(
+                                client,
+                                token,
+                                url,
+                                &guest.node,
+                                kind,
+                                &guest.vmid.to_string(),
+                            )
+                            .await
+                            .map_err(|e| e.to_string())
+                    })
+                    .await?;
+            }
+            retry
+                .run(guest.vmid, || async {
+                    set_identity(client, token, url, lab, kind, guest)
+                        .await
+                        .map_err(|e| e.to_string())
+                })
+                .await?;
+            //Qemu guests only pick the new cloud-init settings up on their next boot.
+            if keys.iter().any(|k| cloudinit::KEYS.contains(k)) {
+                let settings = guest.group.cloudinit.clone().unwrap_or_default();
+                retry
+                    .run(guest.vmid, || async {
+                        cloudinit::apply(
+                            &settings,
+                            client,
+                            token,
+                            url,
+                            &guest.node,
+                            kind,
+                            &guest.name,
+                            guest.index,
+                            guest.vmid,
+                        )
+                        .await
+                        .map_err(|e| e.to_string())
+                    })
+                    .await?;
+            }
+            if let (true, Some(pool)) = (keys.contains(&"pool"), &guest.group.pool) {
+                retry
+                    .run(guest.vmid, || async {
+                        add_to_pool(client, token, url, pool, guest.vmid)
+                            .await
+                            .map_err(|e| e.to_string())
+                    })
+                    .await?;
+            }
+            say!("VMID {} modified", guest.vmid);
+        }
+        Change::Destroy(resource) => {
+            let vmid = resource.vmid.to_string();
+            retry
+                .run(resource.vmid, || async {
+                    let status =
+                        guest_status(client, token, url, &resource.node, &resource.kind, &vmid)
+                            .await
+                            .map_err(|e| e.to_string())?;
+                    if status == "running" {
+                        stop_guest(client, token, url, &resource.node, &resource.kind, &vmid)
+                            .await
+                            .map_err(|e| e.to_string())?;
+                    }
+                    Ok(())
+                })
+                .await?;
+            retry
+                .run(resource.vmid, || async {
+                    destroy_guest(client, token, url, &resource.node, &vmid)
+                        .await
+                        .map_err(|e| e.to_string())
+                })
+                .await?;
+            state.forget(resource.vmid)?;
+            say!("VMID {} destroyed", vmid);
+        }
+    }
+    Ok(())
+}
+
+//Prints what apply would do without touching anything.
+pub async fn plan(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let manifest = match app.get_one::<String>("Manifest") {
+        Some(m) => Manifest::load(m)?,
+        None => panic!("This action requires a manifest"),
+    };
+    let nodename = app.get_one::<String>("Node").unwrap();
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let state = StateFile::from_args(&app).for_manifest(&manifest.lab);
+    let changes = compute_plan(
+        &manifest,
+        &client,
+        &token,
+        &url,
+        nodename,
+        &state.load()?,
+        app.get_flag("Destroy_unmanaged"),
+    )
+    .await?;
+    print_plan(&changes);
+    //Scripts get one planned record per change, with the change as the action.
+    let summary = Summary::single("plan");
+    for change in &changes {
+        let (action, vmid, node, kind) = match change {
+            Change::Create(g) => ("create", g.vmid, &g.node, None),
+            Change::Modify(g, kind, _) => ("modify", g.vmid, &g.node, Some(kind.clone())),
+            Change::Destroy(r) => ("destroy", r.vmid, &r.node, Some(r.kind.clone())),
+        };
+        let mut outcome = summary.start(vmid, node);
+        outcome.action = action.to_owned();
+        outcome.kind = kind;
+        summary.record(outcome, "planned");
+    }
+    summary.finish()
+}
+
+//Computes the plan and carries it out. Destroys go first so VMIDs moved between groups are free
+//by the time they're cloned, then creates and modifies run on the usual throttle.
+pub async fn apply(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let manifest = match app.get_one::<String>("Manifest") {
+        Some(m) => Manifest::load(m)?,
+        None => panic!("This action requires a manifest"),
+    };
+    let nodename = app.get_one::<String>("Node").unwrap();
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let state = StateFile::from_args(&app).for_manifest(&manifest.lab);
+    let changes = compute_plan(
+        &manifest,
+        &client,
+        &token,
+        &url,
+        nodename,
+        &state.load()?,
+        app.get_flag("Destroy_unmanaged"),
+    )
+    .await?;
+    print_plan(&changes);
+    if changes.is_empty() {
+        return Summary::single("apply").finish();
+    }
+    if !app.get_flag("Auto_approve") && !output::confirm("Apply these changes?")? {
+        say!("Apply cancelled.");
+        return Summary::single("apply").finish();
+    }
+    let (destroys, rest): (Vec<Change>, Vec<Change>) = changes
+        .into_iter()
+        .partition(|c| matches!(c, Change::Destroy(_)));
+    let throttle = Throttle::from_args(&app, &client, &token, &url).await?;
+    let retry = Retry::from_args(&app);
+    let summary = Summary::with_progress("apply", destroys.len() + rest.len());
+    for batch in [destroys, rest] {
+        let tasks: Vec<_> = batch
+            .into_iter()
+            .map(|change| {
+                let url = url.clone();
+                let client = client.clone();
+                let token = token.clone();
+                let lab = manifest.lab.clone();
+                let throttle = throttle.clone();
+                let state = state.clone();
+                let summary = summary.clone();
+                tokio::spawn(async move {
+                    let _turn = match &change {
+                        Change::Create(g) => match g.group.template {
+                            Some(t) => {
+                                let node = g.template_node.as_deref().unwrap_or(&g.node);
+                                lxc_clone_turn(&client, &token, &url, node, &t.to_string()).await
+                            }
+                            None => None,
+                        },
+                        _ => None,
+                    };
+                    let _slot = throttle.acquire().await;
+                    let (vmid, node, kind) = match &change {
+                        Change::Create(g) => (g.vmid, &g.node, None),
+                        Change::Modify(g, kind, _) => (g.vmid, &g.node, Some(kind.clone())),
+                        Change::Destroy(r) => (r.vmid, &r.node, Some(r.kind.clone())),
+                    };
+                    let mut outcome = summary.start(vmid, node);
+                    outcome.kind = kind;
+                    let result = apply_change(&change, &client, &token, &url, &lab, &state, retry)
+                        .await
+                        .map_err(|e| e.to_string());
+                    match result {
+                        Ok(_) => summary.ok(outcome),
+                        Err(e) => {
+                            say!("Unable to apply change to VMID {}: {}", vmid, e);
+                            summary.failed(outcome, &e);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await?;
+        }
+    }
+    summary.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn group() -> Group {
+        Group {
+            name: String::from("web"),
+            min: 200,
+            max: 202,
+            template: Some(9000),
+            cores: Some(2),
+            bridge: Some(String::from("vmbr1")),
+            disk_size: Some(String::from("40G")),
+            ..Default::default()
+        }
+    }
+
+    fn manifest(group: Group) -> Manifest {
+        Manifest {
+            lab: String::from("ctf"),
+            node: None,
+            guests: vec![group],
+        }
+    }
+
+    fn config(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    fn keys(diffs: &[Diff]) -> Vec<&str> {
+        diffs.iter().map(|d| d.key.as_str()).collect()
+    }
+
+    #[test]
+    fn desired_names_and_nodes() {
+        let mut lab = manifest(group());
+        lab.node = Some(String::from("pve2"));
+        let desired = lab.desired("pve");
+        assert_eq!(desired.len(), 3);
+        assert_eq!(desired[1].vmid, 201);
+        assert_eq!(desired[1].name, "web1");
+        assert_eq!(desired[1].index, 1);
+        assert_eq!(desired[1].node, "pve2");
+
+        let mut single = group();
+        single.max = 200;
+        single.node = Some(String::from("pve3"));
+        let desired = manifest(single).desired("pve");
+        assert_eq!(desired[0].name, "web");
+        assert_eq!(desired[0].node, "pve3");
+    }
+
+    #[test]
+    fn matching_guest_has_no_diffs() {
+        let desired = &manifest(group()).desired("pve")[0];
+        let live = config(json!({
+            "name": "web0",
+            "cores": 2,
+            "net0": "virtio=AA:BB,bridge=vmbr1",
+            "scsi0": "local-lvm:vm-200-disk-0,size=40960M",
+            "tags": "ctf",
+        }));
+        assert!(diff_config(desired, "ctf", "qemu", &live).is_empty());
+    }
+
+    #[test]
+    fn reports_what_drifted() {
+        let desired = &manifest(group()).desired("pve")[0];
+        let live = config(json!({
+            "name": "web0",
+            "net0": "virtio=AA:BB,bridge=vmbr0",
+            "scsi0": "local-lvm:vm-200-disk-0,size=32G",
+            "tags": "ctf;extra",
+        }));
+        let diffs = diff_config(desired, "ctf", "qemu", &live);
+        assert_eq!(keys(&diffs), ["cores", "net0.bridge", "scsi0.size", "tags"]);
+        assert_eq!(diffs[0].current, "1");
+        assert_eq!(diffs[0].wanted, "2");
+    }
+
+    #[test]
+    fn relative_sizes_and_unset_options_are_not_compared() {
+        let mut group = group();
+        group.cores = None;
+        group.bridge = None;
+        group.disk_size = Some(String::from("+10G"));
+        let desired = &manifest(group).desired("pve")[0];
+        let live = config(json!({
+            "hostname": "web0",
+            "cores": 8,
+            "rootfs": "local-lvm:subvol-200-disk-0,size=8G",
+            "tags": "ctf",
+        }));
+        assert!(diff_config(desired, "ctf", "lxc", &live).is_empty());
+    }
+
+    #[test]
+    fn compares_rendered_cloudinit() {
+        let mut group = group();
+        group.cloudinit = Some(CloudInit {
+            ciuser: Some(String::from("student")),
+            sshkeys: Some(String::from("ssh-ed25519 AAAA+/= me@host\n")),
+            ipconfig0: Some(String::from("ip=10.20.0.{index}/24")),
+            ..Default::default()
+        });
+        let desired = &manifest(group).desired("pve")[2];
+        let live = config(json!({
+            "name": "web2",
+            "cores": 2,
+            "net0": "virtio=AA:BB,bridge=vmbr1",
+            "scsi0": "local-lvm:vm-202-disk-0,size=40G",
+            "tags": "ctf",
+            "ciuser": "student",
+            "sshkeys": "ssh-ed25519%20AAAA%2B%2F%3D%20me%40host%0A",
+            "ipconfig0": "ip=10.20.0.1/24",
+        }));
+        let diffs = diff_config(desired, "ctf", "qemu", &live);
+        assert_eq!(keys(&diffs), ["ipconfig0"]);
+        assert_eq!(diffs[0].wanted, "ip=10.20.0.2/24");
+    }
+
+    #[test]
+    fn sizes_compare_across_units() {
+        assert_eq!(size_in_bytes("40G"), size_in_bytes("40960M"));
+        assert_eq!(size_in_bytes("1T"), Some(1 << 40));
+        assert_eq!(size_in_bytes("512"), Some(512));
+        assert_eq!(size_in_bytes("10X"), None);
+    }
+
+    #[test]
+    fn labs_have_to_work_as_tags() {
+        assert!(check_lab("ctf-2024_a").is_ok());
+        assert!(check_lab("").is_err());
+        assert!(check_lab("CTF").is_err());
+        assert!(check_lab("ctf lab").is_err());
+    }
+}
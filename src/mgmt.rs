@@ -1,22 +1,70 @@
+use super::agent::{self, Readiness};
+use super::cloudinit::{self, CloudInit};
+use super::cluster::{allocate_vmids, get_resources};
+use super::hardware::Hardware;
+use super::ipam::{self, Address, IpPlan};
+use super::journal::{Item, Journal, JournalData};
+use super::preflight;
+use super::retry::Retry;
+use super::state::StateFile;
+use super::summary::Summary;
+use super::throttle::Throttle;
 use clap::ArgMatches;
 use reqwest::header::HeaderMap;
-use reqwest::ClientBuilder;
+use reqwest::{Client, ClientBuilder, RequestBuilder};
 use serde::Deserialize;
 use serde_json::Map;
 use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
 use std::{error::Error, sync::Arc};
-use tokio::sync::Semaphore;
+
+//Proxmox locks an lxc for the whole time it's being cloned, so a second clone of the same container
+//fails on the lock. Each lxc source gets its own queue so its clones run one at a time while qemu
+//clones and clones of other sources keep running in parallel.
+static LXC_CLONE_QUEUES: OnceLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> =
+    OnceLock::new();
+
+fn lxc_clone_queue(name: &str, src: &str) -> Arc<tokio::sync::Mutex<()>> {
+    let queues = LXC_CLONE_QUEUES.get_or_init(|| Mutex::new(HashMap::new()));
+    queues
+        .lock()
+        .unwrap()
+        .entry(format!("{}/{}", name, src))
+        .or_default()
+        .clone()
+}
+
+//Waits for a turn in the source's queue and holds it until the guard is dropped, which callers do
+//once the clone finished since that's when proxmox drops the lock. Parallel runs wait here before
+//taking a throttle slot, so clones queued behind the same lxc don't sit on slots other guests could
+//use. Qemu sources don't queue, and neither do sources whose type can't be looked up since the
+//clone runs into and reports the same error.
+pub async fn lxc_clone_turn(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    name: &str,
+    src: &str,
+) -> Option<tokio::sync::OwnedMutexGuard<()>> {
+    let kind = guest_type(client, headers, url, name, src).await.ok();
+    match kind.as_deref() {
+        Some("lxc") => Some(lxc_clone_queue(name, src).lock_owned().await),
+        _ => None,
+    }
+}
 
 //This struct is simply used to handle instances when the api returns "Data":null.
 #[derive(Deserialize)]
 pub struct NULLData {
+    #[allow(dead_code)]
     data: Option<String>,
 }
 //This struct is to handle the upid, which is the unique identifier proxmox returns when you
 //submit a job.
 #[derive(Deserialize, Debug)]
 pub struct UPIDData {
-    data: String,
+    pub data: String,
 }
 //The next two structs handle the query of a job via the aformetioned upid. Since it returns a key
 //with key pairs we have to build a struct the feeds into a struct.
@@ -29,14 +77,24 @@ pub struct JobData {
 pub struct Job {
     pub exitstatus: String,
 }
+//Used to read /status/current for a guest. Only the status field is needed for now.
+#[derive(Deserialize, Debug)]
+pub struct StatusData {
+    pub data: Status,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Status {
+    pub status: String,
+}
 //This functions creates single clones.
 pub async fn create_clone(app: ArgMatches) -> Result<(), Box<dyn Error>> {
     let nodename = app.get_one::<String>("Node").unwrap();
-    let dst = match app.get_one::<String>("Destination") {
-        Some(n) => n,
+    let newid = match app.get_one::<i32>("Destination") {
+        Some(n) => *n,
         None => panic!("This action requires a destination VMID"),
     };
-    let src = app.get_one::<String>("Source");
+    let src = app.get_one::<i32>("Source");
     let mut url = app.get_one::<String>("Url").unwrap().to_owned(); //Handles the format of https://proxmox/ vs https://proxmox
     if url.ends_with('/') {
         url.pop();
@@ -57,7 +115,7 @@ pub async fn create_clone(app: ArgMatches) -> Result<(), Box<dyn Error>> {
     let src = match src {
         //This is a way to make this parameter a requirement for this function,
         //but not the whole program.
-        Some(e) => e,
+        Some(e) => e.to_string(),
         None => panic!("The argument requires a source VMID"),
     };
     let full = match clone_type.as_str() {
@@ -65,59 +123,45 @@ pub async fn create_clone(app: ArgMatches) -> Result<(), Box<dyn Error>> {
         "full" => true,
         _ => false,
     };
+    let cloudinit = CloudInit::from_args(&app)?;
+    let hardware = Hardware::from_args(&app);
     let client = ClientBuilder::new()
         .danger_accept_invalid_certs(true) //Allows us to ignore the invalid ssl cert
         .build()?;
-    //Using the Map and Value structs from serde_json allows us to have a hashmap with mixed data types.
-    let mut json_data = Map::new();
-    if name != "" {
-        json_data.insert("name".to_string(), Value::String(name.to_owned()));
-    }
-    json_data.insert("newid".to_string(), Value::String(dst.to_owned()));
-    json_data.insert("node".to_string(), Value::String(nodename.to_owned()));
-    json_data.insert(
-        "vmid".to_string(),
-        serde_json::Value::String(src.to_owned()),
-    );
-    json_data.insert("full".to_string(), Value::Bool(full));
-    //The below code sends between 1-2 requests. It doesn't actually implement a way to determine
-    //if the src template is lxc or qemu.
-    let lxc_url = format!("{}/api2/json/nodes/{}/lxc/{}/clone", url, nodename, src);
-    let qemu_url = format!("{}/api2/json/nodes/{}/qemu/{}/clone", url, nodename, src);
-    let qemu_response = client
-        .post(qemu_url)
-        .headers(token.clone())
-        .json(&json_data)
-        .send()
-        .await?;
-    if qemu_response.status() == 200 {
-        //Takes the upid returned by the submitted job and sends it to a function that returns when
-        //the job is finished or errs.
-        let upid: UPIDData =
-            serde_json::de::from_str::<UPIDData>(qemu_response.text().await?.as_str())?;
-        finished(token, upid, &url, nodename).await?;
-    } else {
-        //LCXs can only be single threaded and full cloned at the moment. So pop the clone type
-        //here and replace it with a full clone. Doesn't implement a check for the number of
-        //threads tho.
-        json_data.remove("full");
-        json_data.insert("full".to_string(), Value::Bool(true));
-        let lxc_response = client
-            .post(lxc_url)
-            .headers(token.clone())
-            .json(&json_data)
-            .send()
-            .await?;
-        if lxc_response.status() != 200 {
-            println!("Unable to clone target. Check arguments and permissions.")
-        } else {
-            let upid: UPIDData =
-                serde_json::de::from_str::<UPIDData>(lxc_response.text().await?.as_str())?;
-            finished(token, upid, &url, nodename).await?;
-            println!("VMID {} cloned from {}", dst, src);
+    //A single clone gets the first address of the range.
+    let address = match app.get_one::<String>("Ip_range") {
+        Some(cidr) => {
+            let plan = IpPlan::new(cidr, app.get_one::<String>("Gateway"), &[newid])?;
+            plan.check(&client, &token, &url).await?;
+            plan.address(newid)
         }
-    }
-    Ok(())
+        None => None,
+    };
+    let summary = Summary::single("clone");
+    let mut outcome = summary.start(newid, nodename);
+    let submitted = Mutex::new(None);
+    let on_submit = |_: &str, upid: &str| *submitted.lock().unwrap() = Some(upid.to_owned());
+    let cloned = clone_guest_tracked(
+        &client, &token, &url, nodename, None, &src, newid, name, full, None, &on_submit,
+    )
+    .await;
+    outcome.upid = submitted.lock().unwrap().take();
+    let kind = match cloned {
+        Ok(kind) => kind,
+        Err(e) => {
+            summary.failed(outcome, &e.to_string());
+            return summary.finish();
+        }
+    };
+    say!("VMID {} cloned from {}", newid, src);
+    StateFile::from_args(&app).record(newid, nodename, &kind, Some(&src), name)?;
+    post_clone(
+        &client, &token, &url, nodename, &kind, newid, name, 0, &hardware, &cloudinit, address,
+    )
+    .await;
+    outcome.kind = Some(kind);
+    summary.ok(outcome);
+    summary.finish()
 }
 //This function does much of the same thing as the last one, sends a delete and doesn't send json.
 pub async fn destroy_vm(app: ArgMatches) -> Result<(), Box<dyn Error>> {
@@ -126,7 +170,7 @@ pub async fn destroy_vm(app: ArgMatches) -> Result<(), Box<dyn Error>> {
         .build()?;
     let nodename = app.get_one::<String>("Node").unwrap();
 
-    let src = app.get_one::<String>("Source");
+    let src = app.get_one::<i32>("Source");
     let mut url = app.get_one::<String>("Url").unwrap().to_owned();
     if url.ends_with('/') {
         url.pop();
@@ -135,46 +179,36 @@ pub async fn destroy_vm(app: ArgMatches) -> Result<(), Box<dyn Error>> {
     let password = app.get_one::<String>("Password").unwrap();
     let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
     let src = match src {
-        Some(e) => e,
+        Some(e) => *e,
         None => panic!("The argument requires a source VMID"),
     };
-    let qemu_url = format!("{}/api2/json/nodes/{}/qemu/{}", url, nodename, src);
-    let lxc_url = format!("{}/api2/json/nodes/{}/lxc/{}", url, nodename, src);
-    let qemu_response = client
-        .delete(qemu_url)
-        .headers(token.clone())
-        .send()
-        .await?;
-    if qemu_response.status() == 200 {
-        let upid: UPIDData =
-            serde_json::de::from_str::<UPIDData>(qemu_response.text().await?.as_str())?;
-        finished(token, upid, &url, nodename).await?;
-    } else {
-        let lxc_response = client.delete(lxc_url).headers(token.clone()).send().await?;
-        if lxc_response.status() != 200 {
-            println!("Unable to destroy target. Check arguments and permissions.")
-        } else {
-            let upid: UPIDData =
-                serde_json::de::from_str::<UPIDData>(lxc_response.text().await?.as_str())?;
-            finished(token, upid, &url, nodename).await?;
-            println!("{} destroyed.", src);
+    let summary = Summary::single("destroy");
+    let mut outcome = summary.start(src, nodename);
+    match destroy_guest(&client, &token, &url, nodename, &src.to_string()).await {
+        Ok(upid) => {
+            outcome.upid = Some(upid);
+            summary.ok(outcome);
+        }
+        Err(e) => {
+            summary.failed(outcome, &e.to_string());
+            return summary.finish();
         }
     }
-    Ok(())
+    say!("{} destroyed.", src);
+    StateFile::from_args(&app).forget(src)?;
+    summary.finish()
 }
 //This does much of the same stuff as create_clone, but uses tokio to thread and send requests
-//async.
+//async. Progress is kept in a journal so a run that dies halfway can be picked up with --resume.
 pub async fn bulk_clone(app: ArgMatches) -> Result<(), Box<dyn Error>> {
-    let max = match app.get_one::<String>("Max").unwrap().parse::<i32>() {
-        Ok(i) => i,
-        Err(_) => panic!("Max needs to be a valid VMID"),
-    };
-    let min = match app.get_one::<String>("Min").unwrap().parse::<i32>() {
-        Ok(i) => i,
-        Err(_) => panic!("Min needs to be a valid VMID"),
-    };
-    let nodename = app.get_one::<String>("Node").unwrap();
-    let src = app.get_one::<String>("Source");
+    let max = app.get_one::<i32>("Max").copied();
+    let min = app.get_one::<i32>("Min").copied();
+    let count = app
+        .get_one::<String>("Count")
+        .map(|count| match count.parse::<usize>() {
+            Ok(i) => i,
+            Err(_) => panic!("Count needs to be a number"),
+        });
     let mut url = app.get_one::<String>("Url").unwrap().to_owned();
     if url.ends_with('/') {
         url.pop();
@@ -187,92 +221,227 @@ pub async fn bulk_clone(app: ArgMatches) -> Result<(), Box<dyn Error>> {
         Some(n) => n,
         None => "",
     };
-    let src = match src {
-        Some(e) => e,
-        None => panic!("The argument requires a source VMID"),
-    };
-    let full = match app.get_one::<String>("Clone_type").unwrap().as_str() {
-        "linked" => false,
-        "full" => true,
-        _ => false,
-    };
+    let cloudinit = CloudInit::from_args(&app)?;
+    let hardware = Hardware::from_args(&app);
+    let state = StateFile::from_args(&app);
+    let retry = Retry::from_args(&app);
     let client = reqwest::ClientBuilder::new()
         .danger_accept_invalid_certs(true)
         .build()?;
-    //Creates a semaphore to control the amount of concurrent jobs running.
-    let semaphore = Arc::new(Semaphore::new(
-        app.get_one::<String>("Threads").unwrap().parse::<usize>()?,
-    ));
-    let jobs: Vec<_> = (min..max + 1).collect(); // Creates a vec of the specified range. Inclusive.
-                                                 //Creates a vec of the jobs needed to be accomplished.
-    let tasks: Vec<_> = jobs
-        .into_iter()
-        .map(|newid| {
-            let qemu_url = format!("{}/api2/json/nodes/{}/qemu/{}/clone", url, nodename, src);
-            let lxc_url = format!("{}/api2/json/nodes/{}/lxc/{}/clone", url, nodename, src);
-            let permit = semaphore.clone();
-            let mut json_data = Map::new();
-            let mut temp_name = String::new();
-            if name != "" {
-                temp_name = format!("{}{}", name, (newid - min));
+    //Controls the amount of concurrent jobs running, which can change as we go with --adaptive.
+    let throttle = Throttle::from_args(&app, &client, &token, &url).await?;
+    let journal_path = app.get_one::<String>("Journal").unwrap();
+    //A new run's journal is only written once the checks below pass, so a rejected plan can't be
+    //resumed or block the next run.
+    let (resumed_journal, data) = if app.get_flag("Resume") {
+        let journal = Journal::resume(journal_path)?;
+        let data = journal.data();
+        say!(
+            "Resuming {} of {} clones of {}",
+            data.items.iter().filter(|i| i.status != "done").count(),
+            data.items.len(),
+            data.source
+        );
+        (Some(journal), data)
+    } else {
+        Journal::check_free(journal_path)?;
+        let src = match app.get_one::<i32>("Source") {
+            Some(e) => e.to_string(),
+            None => panic!("The argument requires a source VMID"),
+        };
+        let full = match app.get_one::<String>("Clone_type").unwrap().as_str() {
+            "linked" => false,
+            "full" => true,
+            _ => false,
+        };
+        //With --count the VMIDs are picked from whatever is free, inside min/max if given.
+        //Otherwise it's every VMID in the range, inclusive. Guests are numbered by their place in
+        //the list.
+        let jobs: Vec<i32> = match (count, min, max) {
+            (Some(count), min, max) => {
+                let jobs = allocate_vmids(&client, &token, &url, count, min, max).await?;
+                say!("Allocated VMIDs {:?}", jobs);
+                jobs
+            }
+            (None, Some(min), Some(max)) => (min..max + 1).collect(),
+            _ => panic!("This action requires a min/max range or a count"),
+        };
+        let items = jobs
+            .iter()
+            .enumerate()
+            .map(|(index, newid)| {
+                let mut temp_name = String::new();
+                if !name.is_empty() {
+                    temp_name = format!("{}{}", name, index);
+                }
+                Item::new(*newid, &temp_name, index as i32)
+            })
+            .collect();
+        (
+            None,
+            JournalData {
+                action: String::from("bulk_clone"),
+                source: src.to_owned(),
+                node: app.get_one::<String>("Node").unwrap().to_owned(),
+                full,
+                items,
+            },
+        )
+    };
+    let (src, nodename, full) = (data.source.clone(), data.node.clone(), data.full);
+    //A run that died between proxmox accepting a clone and the journal saying so leaves the item
+    //pending with the guest already there. A guest with the journaled name on the journaled node
+    //is taken as that clone and finished like one that was submitted.
+    let mut adopted: HashMap<i32, String> = HashMap::new();
+    if app.get_flag("Resume") {
+        let resources = get_resources(&client, &token, &url).await?;
+        for item in data
+            .items
+            .iter()
+            .filter(|i| i.status == "pending" && !i.name.is_empty())
+        {
+            let resource = match resources.iter().find(|r| {
+                r.vmid == item.vmid && r.node == nodename && r.name.as_ref() == Some(&item.name)
+            }) {
+                Some(r) => r,
+                None => continue,
+            };
+            if resource.lock.is_some() {
+                return Err(format!(
+                    "VMID {} is still locked by the clone from the last run, resume again once it's done",
+                    item.vmid
+                )
+                .into());
             }
-            json_data.insert(String::from("newid"), Value::String(newid.to_string()));
-            json_data.insert(String::from("node"), Value::String(nodename.clone()));
-            json_data.insert(String::from("vmid"), Value::String(src.clone()));
-            json_data.insert(String::from("full"), Value::Bool(full.clone()));
+            say!(
+                "VMID {} was cloned before the last run died, finishing it",
+                item.vmid
+            );
+            adopted.insert(item.vmid, resource.kind.clone());
+        }
+    }
+    //Guests that were submitted or finished already are left out of the checks since they're
+    //expected to exist.
+    let unstarted: Vec<&Item> = data
+        .items
+        .iter()
+        .filter(|i| i.status == "pending" || i.status == "failed")
+        .filter(|i| !adopted.contains_key(&i.vmid))
+        .collect();
+    let jobs: Vec<i32> = unstarted.iter().map(|i| i.vmid).collect();
+    let names: Vec<String> = unstarted
+        .iter()
+        .filter(|i| !i.name.is_empty())
+        .map(|i| i.name.clone())
+        .collect();
+    if !jobs.is_empty() {
+        preflight::check_bulk_clone(&client, &token, &url, &nodename, &src, &jobs, &names, full)
+            .await?;
+    }
+    //Works out the static addresses up front so an overlap stops the run before anything is cloned.
+    let ip_plan = match app.get_one::<String>("Ip_range") {
+        Some(cidr) => {
+            let all: Vec<i32> = data.items.iter().map(|i| i.vmid).collect();
+            let plan = IpPlan::new(cidr, app.get_one::<String>("Gateway"), &all)?;
+            plan.check(&client, &token, &url).await?;
+            plan.print();
+            Some(plan)
+        }
+        None => None,
+    };
+    let journal = match resumed_journal {
+        Some(journal) => journal,
+        None => Journal::start(journal_path, data.clone())?,
+    };
+    let summary = Summary::with_progress("bulk_clone", data.items.len());
+    for item in data.items.iter().filter(|item| item.status == "done") {
+        let mut outcome = summary.start(item.vmid, &nodename);
+        outcome.kind = item.kind.clone();
+        outcome.upid = item.upid.clone();
+        summary.skipped(outcome, "done in an earlier run");
+    }
+    //Creates a vec of the jobs needed to be accomplished.
+    let tasks: Vec<_> = data
+        .items
+        .into_iter()
+        .filter(|item| item.status != "done")
+        .map(|item| {
+            let address = ip_plan.as_ref().and_then(|p| p.address(item.vmid));
+            let throttle = throttle.clone();
             let url = url.clone();
             let client = client.clone();
             let src = src.clone();
-            let temp_name = temp_name.clone();
             let nodename = nodename.clone();
             let token = token.clone();
+            let cloudinit = cloudinit.clone();
+            let hardware = hardware.clone();
+            let state = state.clone();
+            let journal = journal.clone();
+            let summary = summary.clone();
+            let adopted = adopted.get(&item.vmid).cloned();
             tokio::spawn(async move {
-                let _permit = permit.acquire().await.unwrap();
-                if temp_name != "" {
-                    json_data.insert("name".to_string(), Value::String(temp_name.to_string()));
-                }
-                let qemu_response = client
-                    .clone()
-                    .post(qemu_url)
-                    .headers(token.clone())
-                    .json(&json_data.clone())
-                    .send()
-                    .await
-                    .unwrap();
-
-                if qemu_response.status() == 200 {
-                    let upid: UPIDData = serde_json::de::from_str::<UPIDData>(
-                        qemu_response.text().await.unwrap().as_str(),
-                    )
-                    .unwrap();
-                    finished(token, upid, &url, &nodename).await.unwrap();
-                    println!("VMID {} cloned from {}", newid, src);
-                } else {
-                    json_data.remove("full");
-                    json_data.remove("name");
-                    json_data.insert("full".to_string(), Value::Bool(true));
-                    if temp_name != "" {
-                        json_data
-                            .insert("hostname".to_string(), Value::String(temp_name.to_string()));
+                let _turn = lxc_clone_turn(&client, &token, &url, &nodename, &src).await;
+                let _slot = throttle.acquire().await;
+                let newid = item.vmid;
+                let mut outcome = summary.start(newid, &nodename);
+                let submitted = Mutex::new(item.upid.clone());
+                //A clone that was in flight when the last run died is waited on instead of being
+                //submitted again. If it failed it's cloned again like any other.
+                let resumed = match (&item.status[..], &item.kind, &item.upid) {
+                    _ if adopted.is_some() => adopted,
+                    ("submitted", Some(kind), Some(upid)) => {
+                        let upid = UPIDData { data: upid.clone() };
+                        match exit_status(token.clone(), upid, &url, &nodename).await {
+                            Ok(status) if status == "OK" => Some(kind.clone()),
+                            _ => None,
+                        }
                     }
-                    let lxc_response = client
-                        .post(lxc_url)
-                        .headers(token.clone())
-                        .json(&json_data)
-                        .send()
-                        .await
-                        .unwrap();
-                    if lxc_response.status() != 200 {
-                        println!("Unable to clone target. Check arguments and permissions.")
-                    } else {
-                        let upid: UPIDData = match serde_json::de::from_str::<UPIDData>(
-                            lxc_response.text().await.unwrap().as_str(),
-                        ) {
-                            Ok(u) => u,
-                            Err(e) => panic!("Program paniced because of {}", e),
+                    _ => None,
+                };
+                //The error is turned into a string since Box<dyn Error> can't be held across the
+                //awaits below inside a spawned task.
+                let cloned = match resumed {
+                    Some(kind) => Ok(kind),
+                    None => {
+                        let on_submit = |kind: &str, upid: &str| {
+                            journal.submitted(newid, kind, upid);
+                            *submitted.lock().unwrap() = Some(upid.to_owned());
                         };
-                        finished(token, upid, &url, &nodename).await.unwrap();
-                        println!("VMID {} cloned from {}", newid, src);
+                        retry
+                            .run(newid, || async {
+                                clone_guest_tracked(
+                                    &client, &token, &url, &nodename, None, &src, newid,
+                                    &item.name, full, None, &on_submit,
+                                )
+                                .await
+                                .map_err(|e| e.to_string())
+                            })
+                            .await
+                    }
+                };
+                match cloned {
+                    Ok(kind) => {
+                        if let Err(e) =
+                            state.record(newid, &nodename, &kind, Some(&src), &item.name)
+                        {
+                            say!("Unable to record VMID {} in the state: {}", newid, e);
+                        }
+                        journal.done(newid, &kind);
+                        say!("VMID {} cloned from {}", newid, src);
+                        post_clone(
+                            &client, &token, &url, &nodename, &kind, newid, &item.name, item.index,
+                            &hardware, &cloudinit, address,
+                        )
+                        .await;
+                        outcome.kind = Some(kind);
+                        outcome.upid = submitted.lock().unwrap().take();
+                        summary.ok(outcome);
+                    }
+                    Err(e) => {
+                        journal.failed(newid, &e);
+                        say!("Unable to clone VMID {}: {}", newid, e);
+                        outcome.upid = submitted.lock().unwrap().take();
+                        summary.failed(outcome, &e);
                     }
                 }
             })
@@ -281,18 +450,13 @@ pub async fn bulk_clone(app: ArgMatches) -> Result<(), Box<dyn Error>> {
     for task in tasks {
         task.await?;
     }
-    Ok(())
+    journal.finish()?;
+    summary.finish()
 }
 //Does much of the same as the aformetioned function, but deletes instead.
 pub async fn bulk_destroy(app: ArgMatches) -> Result<(), Box<dyn Error>> {
-    let max = match app.get_one::<String>("Max").unwrap().parse::<i32>() {
-        Ok(i) => i,
-        Err(_) => panic!("Max needs to be a valid VMID"),
-    };
-    let min = match app.get_one::<String>("Min").unwrap().parse::<i32>() {
-        Ok(i) => i,
-        Err(_) => panic!("Min needs to be a valid VMID"),
-    };
+    let max = *app.get_one::<i32>("Max").unwrap();
+    let min = *app.get_one::<i32>("Min").unwrap();
     let client = ClientBuilder::new()
         .danger_accept_invalid_certs(true)
         .build()?;
@@ -304,53 +468,48 @@ pub async fn bulk_destroy(app: ArgMatches) -> Result<(), Box<dyn Error>> {
     let username = app.get_one::<String>("Username").unwrap();
     let password = app.get_one::<String>("Password").unwrap();
     let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
-    let semaphore = Arc::new(Semaphore::new(
-        app.get_one::<String>("Threads").unwrap().parse::<usize>()?,
-    ));
+    let throttle = Throttle::from_args(&app, &client, &token, &url).await?;
+    let state = StateFile::from_args(&app);
+    let retry = Retry::from_args(&app);
     let jobs: Vec<_> = (min..max + 1).collect();
+    let summary = Summary::with_progress("bulk_destroy", jobs.len());
     let tasks: Vec<_> = jobs
         .into_iter()
         .map(|newid| {
-            let qemu_url = format!(
-                "{}/api2/json/nodes/{}/qemu/{}",
-                url,
-                name,
-                newid.to_string()
-            );
-            let lxc_url = format!("{}/api2/json/nodes/{}/lxc/{}", url, name, newid.to_string());
             let url = url.clone();
             let client = client.clone();
             let name = name.clone();
             let token = token.clone();
-            let permit = semaphore.clone();
+            let throttle = throttle.clone();
+            let state = state.clone();
+            let summary = summary.clone();
             tokio::spawn(async move {
-                let _permit = permit.acquire().await.unwrap();
-                let qemu_request =
-                    match client.delete(&qemu_url).headers(token.clone()).send().await {
-                        Ok(c) => c,
-                        Err(_) => panic!("Encountered an error. Does the VMID exist?"),
-                    };
-                if qemu_request.status() == 200 {
-                    let text = qemu_request.text().await.unwrap(); // Same with the aformetioned comment
-                    let upid = serde_json::de::from_str::<UPIDData>(text.as_str()).unwrap();
-                    finished(token.clone(), upid, &url, &name).await.unwrap();
-                    println!("{} destroyed", newid);
-                } else {
-                    let lxc_request =
-                        match client.delete(&lxc_url).headers(token.clone()).send().await {
-                            Ok(c) => c,
-                            Err(_) => panic!("Encountered an error. Does the VMID exist?"),
-                        };
-                    if lxc_request.status() == 200 {
-                        let text = lxc_request.text().await.unwrap(); // Same with the aformetioned comment
-                        let upid = serde_json::de::from_str::<UPIDData>(text.as_str()).unwrap();
-                        finished(token.clone(), upid, &url, &name).await.unwrap();
-                        println!("{} destroyed", newid);
-                    } else {
-                        println!(
-                            "An error occured in starting {}\nMake sure vmid exists",
-                            newid
+                let _slot = throttle.acquire().await;
+                let mut outcome = summary.start(newid, &name);
+                let vmid = newid.to_string();
+                let destroyed = retry
+                    .run(newid, || async {
+                        destroy_guest(&client, &token, &url, &name, &vmid)
+                            .await
+                            .map_err(|e| e.to_string())
+                    })
+                    .await;
+                match destroyed {
+                    Ok(upid) => {
+                        say!("{} destroyed", newid);
+                        if let Err(e) = state.forget(newid) {
+                            say!("Unable to remove {} from the state: {}", newid, e);
+                        }
+                        outcome.upid = Some(upid);
+                        summary.ok(outcome);
+                    }
+                    Err(e) => {
+                        say!(
+                            "An error occured in destroying {}\nMake sure vmid exists: {}",
+                            newid,
+                            e
                         );
+                        summary.failed(outcome, &e);
                     }
                 }
             })
@@ -359,120 +518,127 @@ pub async fn bulk_destroy(app: ArgMatches) -> Result<(), Box<dyn Error>> {
     for task in tasks {
         task.await?;
     }
-    Ok(())
+    summary.finish()
 }
 
-pub async fn bulk_stop(app: ArgMatches) -> Result<(), Box<dyn Error>> {
-    let max = match app.get_one::<String>("Max").unwrap().parse::<i32>() {
-        Ok(i) => i,
-        Err(_) => panic!("Max needs to be a valid VMID"),
-    };
-    let min = match app.get_one::<String>("Min").unwrap().parse::<i32>() {
-        Ok(i) => i,
-        Err(_) => panic!("Min needs to be a valid VMID"),
-    };
-    let client = ClientBuilder::new()
-        .danger_accept_invalid_certs(true)
-        .build()?;
-    let name = app.get_one::<String>("Node").unwrap();
-    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
-    if url.ends_with('/') {
-        url.pop();
+//Clones src into newid and waits for the job to finish. The type of src is looked up first and
+//handed back. Callers cloning the same lxc in parallel need to hold its lxc_clone_turn.
+#[allow(clippy::too_many_arguments)]
+pub async fn clone_guest(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    name: &str,
+    target: Option<&str>,
+    src: &str,
+    newid: i32,
+    guest_name: &str,
+    full: bool,
+    pool: Option<&str>,
+) -> Result<String, Box<dyn Error>> {
+    clone_guest_tracked(
+        client,
+        headers,
+        url,
+        name,
+        target,
+        src,
+        newid,
+        guest_name,
+        full,
+        pool,
+        &|_, _| {},
+    )
+    .await
+}
+
+//Same as clone_guest, but calls on_submit with the guest type and upid as soon as proxmox accepts
+//the clone, before waiting on it. Bulk runs use it to journal the upid.
+#[allow(clippy::too_many_arguments)]
+pub async fn clone_guest_tracked(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    name: &str,
+    target: Option<&str>,
+    src: &str,
+    newid: i32,
+    guest_name: &str,
+    full: bool,
+    pool: Option<&str>,
+    on_submit: &(dyn Fn(&str, &str) + Send + Sync),
+) -> Result<String, Box<dyn Error>> {
+    //Using the Map and Value structs from serde_json allows us to have a hashmap with mixed data types.
+    let mut json_data = Map::new();
+    json_data.insert(String::from("newid"), Value::String(newid.to_string()));
+    json_data.insert(String::from("node"), Value::String(name.to_owned()));
+    json_data.insert(String::from("vmid"), Value::String(src.to_owned()));
+    json_data.insert(String::from("full"), Value::Bool(full));
+    if let Some(pool) = pool {
+        json_data.insert(String::from("pool"), Value::String(pool.to_owned()));
     }
-    let semaphore = Arc::new(Semaphore::new(
-        app.get_one::<String>("Threads").unwrap().parse::<usize>()?,
-    ));
-    let username = app.get_one::<String>("Username").unwrap();
-    let password = app.get_one::<String>("Password").unwrap();
-    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
-    let jobs: Vec<_> = (min..max + 1).collect();
-    let tasks: Vec<_> = jobs
-        .into_iter()
-        .map(|newid| {
-            let qemu_url = format!(
-                "{}/api2/json/nodes/{}/qemu/{}/status/stop",
-                url,
-                name,
-                newid.to_string()
-            );
-            //Starting and stopping things returns a upid and a 200 regardless if the vmid supplied
-            //is actually the correct template type to start/stop. So we make a test url to query
-            //with the vmid to determine the type and then send the request based on that.
-            let checker_url = format!("{}/api2/json/nodes/{}/lxc/{}", url, name, newid.to_string());
-            let lxc_url = format!(
-                "{}/api2/json/nodes/{}/lxc/{}/status/stop",
-                url,
-                name,
-                newid.to_string()
-            );
-            let url = url.clone();
-            let client = client.clone();
-            let name = name.clone();
-            let token = token.clone();
-            let permit = semaphore.clone();
-            tokio::spawn(async move {
-                let _permit = permit.acquire().await.unwrap();
-                //check the type of vm
-                let checker = client
-                    .get(checker_url)
-                    .headers(token.clone())
-                    .send()
-                    .await
-                    .unwrap()
-                    .text()
-                    .await
-                    .unwrap();
-                match serde_json::de::from_str::<NULLData>(checker.as_str()) {
-                    Ok(_) => {
-                        //If the response can correctly serialize as "Data":Null then we assume it's qemu.
-                        let qemu_request =
-                            match client.post(&qemu_url).headers(token.clone()).send().await {
-                                Ok(c) => c,
-                                Err(_) => panic!("Encountered an error. Does the VMID exist?"),
-                            };
-                        if qemu_request.status() == 200 {
-                            let text = qemu_request.text().await.unwrap(); // Same with the aformetioned comment
-                            let upid = serde_json::de::from_str::<UPIDData>(text.as_str()).unwrap();
-                            finished(token.clone(), upid, &url, &name).await.unwrap();
-                            println!("{} stopped", newid);
-                        } else {
-                            println!("Error stopping VMID {}. Does the VM exist?", newid);
-                        }
-                    }
-                    //If the response actually contains data then we assume it's lxc.
-                    Err(_) => {
-                        let lxc_request =
-                            match client.post(&lxc_url).headers(token.clone()).send().await {
-                                Ok(c) => c,
-                                Err(_) => panic!("Encountered an error. Does the VMID exist?"),
-                            };
-                        if lxc_request.status() == 200 {
-                            let text = lxc_request.text().await.unwrap(); // Same with the aformetioned comment
-                            let upid = serde_json::de::from_str::<UPIDData>(text.as_str()).unwrap();
-                            finished(token.clone(), upid, &url, &name).await.unwrap();
-                            println!("{} stopped", newid);
-                        } else {
-                            println!("Error stopping VMID {}. Does the VMID exist?", newid);
-                        }
-                    }
-                };
-            })
-        })
-        .collect();
-    for task in tasks {
-        task.await?;
+    //The clone runs on the source's node and ends up on target.
+    if let Some(target) = target {
+        json_data.insert(String::from("target"), Value::String(target.to_owned()));
     }
-    Ok(())
+    //Only the endpoint for the source's type is called, so an error from it (ie a lock) is the one
+    //that gets retried instead of being hidden behind the other type saying it doesn't exist.
+    let kind = guest_type(client, headers, url, name, src).await?;
+    let clone_url = format!("{}/api2/json/nodes/{}/{}/{}/clone", url, name, kind, src);
+    match kind.as_str() {
+        "lxc" => {
+            //LCXs can only be full cloned at the moment and call their name hostname.
+            json_data.insert("full".to_string(), Value::Bool(true));
+            if !guest_name.is_empty() {
+                json_data.insert("hostname".to_string(), Value::String(guest_name.to_owned()));
+            }
+        }
+        _ => {
+            if !guest_name.is_empty() {
+                json_data.insert("name".to_string(), Value::String(guest_name.to_owned()));
+            }
+        }
+    }
+    let upid = submit_task(
+        client
+            .post(clone_url)
+            .headers(headers.clone())
+            .json(&json_data),
+    )
+    .await?;
+    on_submit(&kind, &upid.data);
+    let status = exit_status(headers.clone(), upid, url, name).await?;
+    if status != "OK" {
+        return Err(status.into());
+    }
+    Ok(kind)
 }
-pub async fn bulk_start(app: ArgMatches) -> Result<(), Box<dyn Error>> {
-    let max = match app.get_one::<String>("Max").unwrap().parse::<i32>() {
-        Ok(i) => i,
-        Err(_) => panic!("Max needs to be a valid VMID"),
-    };
-    let min = match app.get_one::<String>("Min").unwrap().parse::<i32>() {
-        Ok(i) => i,
-        Err(_) => panic!("Min needs to be a valid VMID"),
-    };
+
+//Deletes a guest and waits for the job to finish. Like clone_guest it looks up the type first.
+//Hands back the upid of the delete.
+pub async fn destroy_guest(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    name: &str,
+    vmid: &str,
+) -> Result<String, Box<dyn Error>> {
+    let kind = guest_type(client, headers, url, name, vmid).await?;
+    let destroy_url = format!("{}/api2/json/nodes/{}/{}/{}", url, name, kind, vmid);
+    let upid = submit_task(client.delete(destroy_url).headers(headers.clone())).await?;
+    let id = upid.data.clone();
+    let status = exit_status(headers.clone(), upid, url, name).await?;
+    if status != "OK" {
+        return Err(status.into());
+    }
+    Ok(id)
+}
+
+//Starts or stops every guest in the min/max range. The type of each guest is looked up first
+//since the start and stop endpoints hand back a upid even for the wrong type.
+async fn bulk_power(app: ArgMatches, action: &'static str) -> Result<(), Box<dyn Error>> {
+    let max = *app.get_one::<i32>("Max").unwrap();
+    let min = *app.get_one::<i32>("Min").unwrap();
     let client = ClientBuilder::new()
         .danger_accept_invalid_certs(true)
         .build()?;
@@ -481,92 +647,98 @@ pub async fn bulk_start(app: ArgMatches) -> Result<(), Box<dyn Error>> {
     if url.ends_with('/') {
         url.pop();
     }
-    let semaphore = Arc::new(Semaphore::new(
-        app.get_one::<String>("Threads").unwrap().parse::<usize>()?,
-    ));
+    let retry = Retry::from_args(&app);
     let username = app.get_one::<String>("Username").unwrap();
     let password = app.get_one::<String>("Password").unwrap();
     let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let throttle = Throttle::from_args(&app, &client, &token, &url).await?;
+    let readiness = match action {
+        "start" => Readiness::from_args(&app)?,
+        _ => None,
+    };
     let jobs: Vec<_> = (min..max + 1).collect();
+    let summary = Summary::with_progress(&format!("bulk_{}", action), jobs.len());
     let tasks: Vec<_> = jobs
         .into_iter()
         .map(|newid| {
-            let qemu_url = format!(
-                "{}/api2/json/nodes/{}/qemu/{}/status/start",
-                url,
-                name,
-                newid.to_string()
-            );
-
-            let checker_url = format!("{}/api2/json/nodes/{}/lxc/{}", url, name, newid.to_string());
-            let lxc_url = format!(
-                "{}/api2/json/nodes/{}/lxc/{}/status/start",
-                url,
-                name,
-                newid.to_string()
-            );
             let url = url.clone();
             let client = client.clone();
             let name = name.clone();
             let token = token.clone();
-            let permit = semaphore.clone();
+            let throttle = throttle.clone();
+            let summary = summary.clone();
             tokio::spawn(async move {
-                let _permit = permit.acquire().await.unwrap();
-                //check the type of vm
-                let checker = client
-                    .get(checker_url)
-                    .headers(token.clone())
-                    .send()
-                    .await
-                    .unwrap()
-                    .text()
-                    .await
-                    .unwrap();
-                match serde_json::de::from_str::<NULLData>(checker.as_str()) {
-                    Ok(_) => {
-                        let qemu_request =
-                            match client.post(&qemu_url).headers(token.clone()).send().await {
-                                Ok(c) => c,
-                                Err(_) => panic!("Encountered an error. Does the VMID exist?"),
-                            };
-                        if qemu_request.status() == 200 {
-                            let text = qemu_request.text().await.unwrap(); // Same with the aformetioned comment
-                            let upid = serde_json::de::from_str::<UPIDData>(text.as_str()).unwrap();
-                            finished(token.clone(), upid, &url, &name).await.unwrap();
-                            println!("{} started", newid);
-                        }
-                    }
-                    Err(_) => {
-                        let lxc_request =
-                            match client.post(&lxc_url).headers(token.clone()).send().await {
-                                Ok(c) => c,
-                                Err(_) => panic!("Encountered an error. Does the VMID exist?"),
-                            };
-                        if lxc_request.status() == 200 {
-                            let text = lxc_request.text().await.unwrap(); // Same with the aformetioned comment
-                            let upid = serde_json::de::from_str::<UPIDData>(text.as_str()).unwrap();
-                            finished(token.clone(), upid, &url, &name).await.unwrap();
-                            println!("{} started", newid);
-                        } else {
-                            println!("Error starting VMID {}. Does the LXC exist?", newid);
-                        }
+                let slot = throttle.acquire().await;
+                let mut outcome = summary.start(newid, &name);
+                let vmid = newid.to_string();
+                let result = retry
+                    .run(newid, || async {
+                        let kind = guest_type(&client, &token, &url, &name, &vmid)
+                            .await
+                            .map_err(|e| e.to_string())?;
+                        let upid = power_guest(&client, &token, &url, &name, &kind, &vmid, action)
+                            .await
+                            .map_err(|e| e.to_string())?;
+                        Ok((kind, upid))
+                    })
+                    .await;
+                //Waiting doesn't load the cluster, so the slot goes to the next start meanwhile.
+                drop(slot);
+                let result = match (result, readiness) {
+                    (Ok((kind, upid)), Some(readiness)) => {
+                        say!("{} started, waiting until it's ready", newid);
+                        agent::wait_ready(&client, &token, &url, &name, &kind, newid, readiness)
+                            .await
+                            .map(|address| (kind, upid, address))
+                            .map_err(|e| format!("started but {}", e))
                     }
+                    (result, _) => result.map(|(kind, upid)| (kind, upid, None)),
                 };
+                match result {
+                    Ok((kind, upid, address)) => {
+                        let done = match (action, readiness, address) {
+                            (_, _, Some(address)) => format!("ready on {}", address),
+                            ("start", Some(_), None) => String::from("ready"),
+                            ("start", None, None) => String::from("started"),
+                            _ => String::from("stopped"),
+                        };
+                        say!("{} {}", newid, done);
+                        outcome.kind = Some(kind);
+                        outcome.upid = Some(upid);
+                        summary.ok(outcome);
+                    }
+                    Err(e) => {
+                        say!("Error running {} on VMID {}: {}", action, newid, e);
+                        summary.failed(outcome, &e);
+                    }
+                }
             })
         })
         .collect();
     for task in tasks {
         task.await?;
     }
-    Ok(())
+    summary.finish()
+}
+
+pub async fn bulk_stop(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    bulk_power(app, "stop").await
 }
 
-pub async fn finished(
+pub async fn bulk_start(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    bulk_power(app, "start").await
+}
+
+//How many polls in a row exit_status lets fail with a 5xx before giving up on a task.
+const MAX_POLL_ERRORS: u32 = 10;
+
+//Waits on a upid and hands the exitstatus back so callers can bail out when a job fails.
+pub async fn exit_status(
     headers: HeaderMap,
     upid: UPIDData,
-    url: &String,
-    name: &String,
-) -> Result<(), Box<dyn Error>> {
+    url: &str,
+    name: &str,
+) -> Result<String, Box<dyn Error>> {
     tokio::time::sleep(tokio::time::Duration::from_millis(350)).await;
     let n_url = format!(
         "{}/api2/json/nodes/{}/tasks/{}/status",
@@ -575,25 +747,283 @@ pub async fn finished(
     let client = ClientBuilder::new()
         .danger_accept_invalid_certs(true)
         .build()?;
+    let mut errors = 0;
     loop {
-        let resp = client
+        let response = client
             .get(n_url.clone())
             .headers(headers.clone())
             .send()
-            .await?
-            .text()
             .await?;
-        let job_details = match serde_json::de::from_str::<JobData>(resp.as_str()) {
-            Ok(jobdata) => jobdata,
-            Err(_) => continue,
+        let status = response.status();
+        let resp = response.text().await?;
+        //A busy server can fail a poll now and then, but a 401 or a task that's gone won't fix
+        //itself.
+        if status != 200 {
+            errors += 1;
+            if !status.is_server_error() || errors >= MAX_POLL_ERRORS {
+                return Err(format!(
+                    "{} Unable to get the status of {}: {}",
+                    status, upid.data, resp
+                )
+                .into());
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+            continue;
+        }
+        errors = 0;
+        //Running jobs don't have an exitstatus yet, so wait a bit and ask again.
+        match serde_json::de::from_str::<JobData>(resp.as_str()) {
+            Ok(jobdata) => return Ok(jobdata.data.exitstatus),
+            Err(_) => tokio::time::sleep(tokio::time::Duration::from_millis(500)).await,
         };
-        if job_details.data.exitstatus == String::from("OK") {
-            break;
-        } else {
-            println!("{:?}", job_details.data.exitstatus);
-            break;
+    }
+}
+
+//Runs the optional steps that come after a clone finishes: hardware changes, cloud-init and
+//static addresses. Failures are printed instead of returned so one bad clone doesn't stop the
+//rest of a bulk run.
+#[allow(clippy::too_many_arguments)]
+pub async fn post_clone(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    name: &str,
+    kind: &str,
+    newid: i32,
+    guest_name: &str,
+    index: i32,
+    hardware: &Hardware,
+    cloudinit: &CloudInit,
+    address: Option<Address>,
+) {
+    let vmid = newid.to_string();
+    if !hardware.is_empty() {
+        if let Err(e) = hardware
+            .apply(client, headers, url, name, kind, &vmid)
+            .await
+        {
+            say!("Unable to update hardware on VMID {}: {}", newid, e);
         }
     }
+    if !cloudinit.is_empty() {
+        if let Err(e) = cloudinit::apply(
+            cloudinit, client, headers, url, name, kind, guest_name, index, newid,
+        )
+        .await
+        {
+            say!("Unable to apply cloud-init to VMID {}: {}", newid, e);
+        }
+    }
+    if let Some(address) = address {
+        if let Err(e) = ipam::apply(client, headers, url, name, kind, newid, &address).await {
+            say!("Unable to assign an address to VMID {}: {}", newid, e);
+        }
+    }
+}
+
+//Hard stops a guest and waits for it to go down. Used before destroying guests that are running.
+pub async fn stop_guest(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    name: &str,
+    kind: &str,
+    vmid: &str,
+) -> Result<(), Box<dyn Error>> {
+    power_guest(client, headers, url, name, kind, vmid, "stop").await?;
+    Ok(())
+}
+
+//Runs one of the status actions (start, stop, shutdown...), waits for it to finish and hands back
+//its upid.
+pub async fn power_guest(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    name: &str,
+    kind: &str,
+    vmid: &str,
+    action: &str,
+) -> Result<String, Box<dyn Error>> {
+    let power_url = format!(
+        "{}/api2/json/nodes/{}/{}/{}/status/{}",
+        url, name, kind, vmid, action
+    );
+    let upid = submit_task(client.post(power_url).headers(headers.clone())).await?;
+    let id = upid.data.clone();
+    let status = exit_status(headers.clone(), upid, url, name).await?;
+    if status != "OK" {
+        return Err(status.into());
+    }
+    Ok(id)
+}
+
+//Sends a request for a job and hands back the upid, or the error text proxmox gave us.
+pub async fn submit_task(request: RequestBuilder) -> Result<UPIDData, Box<dyn Error>> {
+    let response = request.send().await?;
+    if response.status() != 200 {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        return Err(format!("{} {}", status, text).into());
+    }
+    Ok(serde_json::de::from_str::<UPIDData>(
+        response.text().await?.as_str(),
+    )?)
+}
 
+//Updates the config of a guest. The PUT endpoint applies the changes right away instead of
+//handing back a upid.
+pub async fn set_config(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    name: &str,
+    kind: &str,
+    vmid: &str,
+    json_data: &Map<String, Value>,
+) -> Result<(), Box<dyn Error>> {
+    let config_url = format!("{}/api2/json/nodes/{}/{}/{}/config", url, name, kind, vmid);
+    let response = client
+        .put(config_url)
+        .headers(headers.clone())
+        .json(json_data)
+        .send()
+        .await?;
+    if response.status() != 200 {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        return Err(format!("{} {}", status, text).into());
+    }
     Ok(())
 }
+
+//Handles the config of a guest. The keys differ a lot between qemu and lxc so it's kept as a map.
+#[derive(Deserialize, Debug)]
+pub struct ConfigData {
+    pub data: Map<String, Value>,
+}
+
+//Returns the current config of a guest.
+pub async fn guest_config(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    name: &str,
+    kind: &str,
+    vmid: &str,
+) -> Result<Map<String, Value>, Box<dyn Error>> {
+    let config_url = format!("{}/api2/json/nodes/{}/{}/{}/config", url, name, kind, vmid);
+    let text = client
+        .get(config_url)
+        .headers(headers.clone())
+        .send()
+        .await?
+        .text()
+        .await?;
+    let config = serde_json::de::from_str::<ConfigData>(text.as_str())?;
+    Ok(config.data)
+}
+
+//GETs anything under a guest, ie status/current or snapshot, and hands back its data. Proxmox
+//answers 500 when the qemu guest agent isn't running, so anything but a success is an error here.
+pub async fn guest_get(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    name: &str,
+    kind: &str,
+    vmid: &str,
+    path: &str,
+) -> Result<Value, Box<dyn Error>> {
+    let get_url = format!(
+        "{}/api2/json/nodes/{}/{}/{}/{}",
+        url, name, kind, vmid, path
+    );
+    let response = client.get(get_url).headers(headers.clone()).send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("{} on VMID {} answered {}", path, vmid, status).into());
+    }
+    let text = response.text().await?;
+    let data = serde_json::de::from_str::<Map<String, Value>>(text.as_str())?;
+    Ok(data.get("data").cloned().unwrap_or(Value::Null))
+}
+
+//POSTs to anything under a guest that answers right away instead of with a upid, like the guest
+//agent endpoints, and hands back its data.
+#[allow(clippy::too_many_arguments)]
+pub async fn guest_post(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    name: &str,
+    kind: &str,
+    vmid: &str,
+    path: &str,
+    json_data: &Map<String, Value>,
+) -> Result<Value, Box<dyn Error>> {
+    let post_url = format!(
+        "{}/api2/json/nodes/{}/{}/{}/{}",
+        url, name, kind, vmid, path
+    );
+    let response = client
+        .post(post_url)
+        .headers(headers.clone())
+        .json(json_data)
+        .send()
+        .await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("{} on VMID {} answered {}", path, vmid, status).into());
+    }
+    let text = response.text().await?;
+    let data = serde_json::de::from_str::<Map<String, Value>>(text.as_str())?;
+    Ok(data.get("data").cloned().unwrap_or(Value::Null))
+}
+
+//Works out if a vmid is a qemu vm or a lxc using the same trick as bulk_start and bulk_stop. The
+//lxc endpoint returns "Data":null for anything that isn't a container.
+pub async fn guest_type(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    name: &str,
+    vmid: &str,
+) -> Result<String, Box<dyn Error>> {
+    let checker_url = format!("{}/api2/json/nodes/{}/lxc/{}", url, name, vmid);
+    let checker = client
+        .get(checker_url)
+        .headers(headers.clone())
+        .send()
+        .await?
+        .text()
+        .await?;
+    match serde_json::de::from_str::<NULLData>(checker.as_str()) {
+        Ok(_) => Ok(String::from("qemu")),
+        Err(_) => Ok(String::from("lxc")),
+    }
+}
+
+//Returns the current state of a guest, ie "running" or "stopped".
+pub async fn guest_status(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    name: &str,
+    kind: &str,
+    vmid: &str,
+) -> Result<String, Box<dyn Error>> {
+    let status_url = format!(
+        "{}/api2/json/nodes/{}/{}/{}/status/current",
+        url, name, kind, vmid
+    );
+    let text = client
+        .get(status_url)
+        .headers(headers.clone())
+        .send()
+        .await?
+        .text()
+        .await?;
+    let status = serde_json::de::from_str::<StatusData>(text.as_str())?;
+    Ok(status.data.status)
+}
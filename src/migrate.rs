@@ -0,0 +1,274 @@
+use super::mgmt::{exit_status, guest_status, guest_type, submit_task};
+use super::retry::Retry;
+use super::summary::Summary;
+use super::throttle::Throttle;
+use clap::ArgMatches;
+use reqwest::header::HeaderMap;
+use reqwest::{Client, ClientBuilder};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::error::Error;
+
+//Used for listing the guests that live on a node when evacuating it. Both the qemu and lxc list
+//endpoints return the same shape for the fields we care about.
+#[derive(Deserialize, Debug)]
+pub struct GuestListData {
+    pub data: Vec<GuestEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GuestEntry {
+    pub vmid: Value,
+    pub name: Option<String>,
+}
+
+//Migrates one guest to the target node. Running qemu guests are live migrated when online is set,
+//running lxcs get a restart migration since containers can't be moved live. Hands back the guest
+//type and the upid of the migration.
+#[allow(clippy::too_many_arguments)]
+pub async fn migrate_guest(
+    client: &Client,
+    token: &HeaderMap,
+    url: &str,
+    nodename: &str,
+    vmid: &str,
+    target: &str,
+    target_storage: Option<&String>,
+    online: bool,
+) -> Result<(String, String), Box<dyn Error>> {
+    let kind = guest_type(client, token, url, nodename, vmid).await?;
+    let running = guest_status(client, token, url, nodename, &kind, vmid).await? == "running";
+    let mut json_data = Map::new();
+    json_data.insert("target".to_string(), Value::String(target.to_owned()));
+    if kind == "qemu" {
+        if running && online {
+            json_data.insert("online".to_string(), Value::Bool(true));
+            json_data.insert("with-local-disks".to_string(), Value::Bool(true));
+        }
+        if let Some(storage) = target_storage {
+            json_data.insert(
+                "targetstorage".to_string(),
+                Value::String(storage.to_owned()),
+            );
+        }
+    } else {
+        if running && online {
+            json_data.insert("restart".to_string(), Value::Bool(true));
+        }
+        if let Some(storage) = target_storage {
+            json_data.insert(
+                "target-storage".to_string(),
+                Value::String(storage.to_owned()),
+            );
+        }
+    }
+    let migrate_url = format!(
+        "{}/api2/json/nodes/{}/{}/{}/migrate",
+        url, nodename, kind, vmid
+    );
+    //Migration tasks run on the source node, so that is where we poll for the upid.
+    let upid = submit_task(
+        client
+            .post(migrate_url)
+            .headers(token.clone())
+            .json(&json_data),
+    )
+    .await?;
+    let id = upid.data.clone();
+    let status = exit_status(token.clone(), upid, url, nodename).await?;
+    if status != "OK" {
+        return Err(format!("Migrating {} failed: {}", vmid, status).into());
+    }
+    say!("VMID {} migrated from {} to {}", vmid, nodename, target);
+    Ok((kind, id))
+}
+
+//Migrates a single guest. Source is the VMID and Target is the node it should end up on.
+pub async fn migrate(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let nodename = app.get_one::<String>("Node").unwrap();
+    let src = match app.get_one::<i32>("Source") {
+        Some(s) => *s,
+        None => panic!("This action requires a source VMID"),
+    };
+    let target = match app.get_one::<String>("Target") {
+        Some(t) => t,
+        None => panic!("This action requires a target node"),
+    };
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let summary = Summary::single("migrate");
+    let mut outcome = summary.start(src, nodename);
+    let migrated = migrate_guest(
+        &client,
+        &token,
+        &url,
+        nodename,
+        &src.to_string(),
+        target,
+        app.get_one::<String>("Target_storage"),
+        app.get_flag("Online"),
+    )
+    .await;
+    match migrated {
+        Ok((kind, upid)) => {
+            outcome.kind = Some(kind);
+            outcome.upid = Some(upid);
+            summary.ok(outcome);
+        }
+        Err(e) => summary.failed(outcome, &e.to_string()),
+    }
+    summary.finish()
+}
+
+//Migrates every guest in the min/max range to the target node.
+pub async fn bulk_migrate(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let max = *app.get_one::<i32>("Max").unwrap();
+    let min = *app.get_one::<i32>("Min").unwrap();
+    let jobs: Vec<String> = (min..max + 1).map(|vmid| vmid.to_string()).collect();
+    let target = match app.get_one::<String>("Target") {
+        Some(t) => vec![t.to_owned()],
+        None => panic!("This action requires a target node"),
+    };
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let online = app.get_flag("Online");
+    run_migrations(&app, "bulk_migrate", token, url, jobs, target, online).await
+}
+
+//Moves everything off of Node before maintenance. Target can be a comma separated list of nodes
+//and guests will be spread over them round robin. Running guests are always migrated online
+//(or restarted for lxcs) since the point is to keep them up.
+pub async fn evacuate(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let nodename = app.get_one::<String>("Node").unwrap();
+    let targets: Vec<String> = match app.get_one::<String>("Target") {
+        Some(t) => t
+            .split(',')
+            .map(|n| n.trim().to_string())
+            .filter(|n| !n.is_empty() && n != nodename)
+            .collect(),
+        None => panic!("This action requires at least one target node"),
+    };
+    if targets.is_empty() {
+        panic!("Evacuating a node needs a target other than the node itself");
+    }
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let mut jobs = Vec::new();
+    for kind in ["qemu", "lxc"] {
+        let list_url = format!("{}/api2/json/nodes/{}/{}", url, nodename, kind);
+        let text = client
+            .get(list_url)
+            .headers(token.clone())
+            .send()
+            .await?
+            .text()
+            .await?;
+        let guests = serde_json::de::from_str::<GuestListData>(text.as_str())?;
+        for guest in guests.data {
+            let vmid = match guest.vmid {
+                Value::String(s) => s,
+                v => v.to_string(),
+            };
+            say!(
+                "Queued {} {} ({}) for evacuation",
+                kind,
+                vmid,
+                guest.name.unwrap_or_default()
+            );
+            jobs.push(vmid);
+        }
+    }
+    jobs.sort_by_key(|vmid| vmid.parse::<i32>().unwrap_or_default());
+    run_migrations(&app, "evacuate", token, url, jobs, targets, true).await
+}
+
+//Shared by bulk_migrate and evacuate. Uses the same throttle as the other bulk actions.
+async fn run_migrations(
+    app: &ArgMatches,
+    action: &str,
+    token: HeaderMap,
+    url: String,
+    jobs: Vec<String>,
+    targets: Vec<String>,
+    online: bool,
+) -> Result<(), Box<dyn Error>> {
+    let nodename = app.get_one::<String>("Node").unwrap();
+    let target_storage = app.get_one::<String>("Target_storage").cloned();
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let throttle = Throttle::from_args(app, &client, &token, &url).await?;
+    let retry = Retry::from_args(app);
+    let summary = Summary::with_progress(action, jobs.len());
+    let tasks: Vec<_> = jobs
+        .into_iter()
+        .enumerate()
+        .map(|(i, vmid)| {
+            let target = targets[i % targets.len()].clone();
+            let url = url.clone();
+            let client = client.clone();
+            let nodename = nodename.clone();
+            let token = token.clone();
+            let target_storage = target_storage.clone();
+            let throttle = throttle.clone();
+            let summary = summary.clone();
+            tokio::spawn(async move {
+                let _slot = throttle.acquire().await;
+                let newid = vmid.parse::<i32>().unwrap_or_default();
+                let mut outcome = summary.start(newid, &nodename);
+                let migrated = retry
+                    .run(newid, || async {
+                        migrate_guest(
+                            &client,
+                            &token,
+                            &url,
+                            &nodename,
+                            &vmid,
+                            &target,
+                            target_storage.as_ref(),
+                            online,
+                        )
+                        .await
+                        .map_err(|e| e.to_string())
+                    })
+                    .await;
+                match migrated {
+                    Ok((kind, upid)) => {
+                        outcome.kind = Some(kind);
+                        outcome.upid = Some(upid);
+                        summary.ok(outcome);
+                    }
+                    Err(e) => {
+                        say!("Error migrating VMID {}: {}", vmid, e);
+                        summary.failed(outcome, &e);
+                    }
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await?;
+    }
+    summary.finish()
+}
@@ -0,0 +1,167 @@
+use clap::ArgMatches;
+use serde::de::{Deserializer, MapAccess, Visitor};
+use serde::ser::{SerializeMap, Serializer};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+use std::sync::OnceLock;
+
+//Text is what terrabad has always printed. Json, ndjson and csv put records on stdout for scripts
+//and move everything meant for people to stderr.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Text,
+    Json,
+    Ndjson,
+    Csv,
+}
+
+//A record as a list of fields. Unlike serde_json's Map it keeps the fields in the order they were
+//written, which csv needs for its header.
+#[derive(Debug, Clone, Default)]
+pub struct Record(pub Vec<(String, Value)>);
+
+impl Serialize for Record {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in &self.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Record {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Record, D::Error> {
+        struct RecordVisitor;
+        impl<'de> Visitor<'de> for RecordVisitor {
+            type Value = Record;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an object")
+            }
+            fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Record, A::Error> {
+                let mut fields = Vec::new();
+                while let Some(field) = access.next_entry::<String, Value>()? {
+                    fields.push(field);
+                }
+                Ok(Record(fields))
+            }
+        }
+        deserializer.deserialize_map(RecordVisitor)
+    }
+}
+
+static FORMAT: OnceLock<Format> = OnceLock::new();
+
+//Prints a line meant for people. It goes to stdout in text mode and stderr otherwise, so stdout
+//only ever holds records when a machine is reading it.
+macro_rules! say {
+    ($($arg:tt)*) => {{
+        $crate::progress::clear();
+        if $crate::output::machine() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    }};
+}
+
+pub fn init(app: &ArgMatches) {
+    let format = match app.get_one::<String>("Output").map(|o| o.as_str()) {
+        Some("json") => Format::Json,
+        Some("ndjson") => Format::Ndjson,
+        Some("csv") => Format::Csv,
+        _ => Format::Text,
+    };
+    FORMAT.get_or_init(|| format);
+}
+
+pub fn format() -> Format {
+    FORMAT.get().copied().unwrap_or(Format::Text)
+}
+
+pub fn machine() -> bool {
+    format() != Format::Text
+}
+
+//Writes one record as a line of its own. Used by ndjson to stream results as they happen.
+pub fn line<T: Serialize>(record: &T) {
+    super::progress::clear();
+    match serde_json::to_string(record) {
+        Ok(text) => println!("{}", text),
+        Err(e) => eprintln!("Unable to write a record: {}", e),
+    }
+}
+
+//Writes a finished set of records, as one array for json, a line each for ndjson or a header and
+//rows for csv. Text mode leaves it to the caller to print a table.
+pub fn records<T: Serialize>(records: &[T]) -> Result<(), Box<dyn Error>> {
+    match format() {
+        Format::Json => println!("{}", serde_json::to_string_pretty(records)?),
+        Format::Ndjson => records.iter().for_each(line),
+        Format::Csv => {
+            let rows = records
+                .iter()
+                .map(|r| serde_json::from_str::<Record>(&serde_json::to_string(r)?))
+                .collect::<Result<Vec<Record>, _>>()?;
+            print_csv(&rows);
+        }
+        Format::Text => {}
+    }
+    Ok(())
+}
+
+//The header comes from the first record, so every record needs the same fields. Lists are joined
+//with ; and nested objects are written as json.
+fn print_csv(rows: &[Record]) {
+    let header = match rows.first() {
+        Some(row) => row
+            .0
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .collect::<Vec<_>>(),
+        None => return,
+    };
+    println!("{}", header.join(","));
+    for row in rows {
+        let cells: Vec<String> = row.0.iter().map(|(_, value)| csv_cell(value)).collect();
+        println!("{}", cells.join(","));
+    }
+}
+
+fn csv_cell(value: &Value) -> String {
+    let text = match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.to_owned(),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                Value::String(s) => s.to_owned(),
+                other => other.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(";"),
+        other => other.to_string(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+//Asks a yes or no question the same place say! would print it and reads the answer from stdin.
+pub fn confirm(question: &str) -> Result<bool, Box<dyn Error>> {
+    if machine() {
+        eprint!("{} Only 'yes' will be accepted: ", question);
+        std::io::stderr().flush()?;
+    } else {
+        print!("{} Only 'yes' will be accepted: ", question);
+        std::io::stdout().flush()?;
+    }
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim() == "yes")
+}
@@ -0,0 +1,193 @@
+use super::cluster::get_resources;
+use super::manifest::{device_value, size_in_bytes, value_string};
+use super::mgmt::guest_config;
+use reqwest::header::HeaderMap;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+
+//Handles /access/permissions, a map of path to the privileges the user has there.
+#[derive(Deserialize, Debug)]
+struct PermissionData {
+    data: BTreeMap<String, BTreeMap<String, Value>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct StorageStatusData {
+    data: StorageStatus,
+}
+
+#[derive(Deserialize, Debug)]
+struct StorageStatus {
+    avail: u64,
+}
+
+//The privileges the user ends up with on a path, inherited ones included.
+async fn privileges(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    path: &str,
+) -> Result<HashSet<String>, Box<dyn Error>> {
+    let permission_url = format!("{}/api2/json/access/permissions?path={}", url, path);
+    let text = client
+        .get(permission_url)
+        .headers(headers.clone())
+        .send()
+        .await?
+        .text()
+        .await?;
+    let permissions = serde_json::de::from_str::<PermissionData>(text.as_str())?;
+    Ok(permissions
+        .data
+        .into_values()
+        .flat_map(|privs| privs.into_keys())
+        .collect())
+}
+
+async fn storage_avail(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    nodename: &str,
+    storage: &str,
+) -> Result<u64, Box<dyn Error>> {
+    let status_url = format!(
+        "{}/api2/json/nodes/{}/storage/{}/status",
+        url, nodename, storage
+    );
+    let text = client
+        .get(status_url)
+        .headers(headers.clone())
+        .send()
+        .await?
+        .text()
+        .await?;
+    Ok(
+        serde_json::de::from_str::<StorageStatusData>(text.as_str())?
+            .data
+            .avail,
+    )
+}
+
+//Adds up the disks of a guest per storage. Cdroms and cloud-init drives are skipped since they
+//don't take up space worth counting.
+fn disk_usage(config: &Map<String, Value>) -> BTreeMap<String, u64> {
+    let mut usage = BTreeMap::new();
+    for (key, value) in config {
+        let is_disk = ["scsi", "virtio", "sata", "ide", "efidisk", "tpmstate", "mp"]
+            .iter()
+            .any(|bus| {
+                key.strip_prefix(bus)
+                    .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+            })
+            || key == "rootfs";
+        let device = value_string(Some(value));
+        if !is_disk || device.contains("media=cdrom") || device.contains("cloudinit") {
+            continue;
+        }
+        let storage = match device.split_once(':') {
+            Some((storage, _)) => storage.to_owned(),
+            None => continue,
+        };
+        let size = size_in_bytes(&device_value(&device, "size")).unwrap_or_default();
+        *usage.entry(storage).or_insert(0) += size;
+    }
+    usage
+}
+
+//Checks everything bulk_clone can know up front and fails with every problem at once, before a
+//single clone is submitted. Names are only checked when they're set.
+#[allow(clippy::too_many_arguments)]
+pub async fn check_bulk_clone(
+    client: &Client,
+    headers: &HeaderMap,
+    url: &str,
+    nodename: &str,
+    src: &str,
+    jobs: &[i32],
+    names: &[String],
+    full: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut problems = Vec::new();
+    let resources = get_resources(client, headers, url).await?;
+    let source = resources.iter().find(|r| r.vmid.to_string() == src);
+    match source {
+        None => problems.push(format!("Source {} doesn't exist", src)),
+        Some(source) => {
+            if source.node != nodename {
+                problems.push(format!(
+                    "Source {} is on {}, not {}",
+                    src, source.node, nodename
+                ));
+            }
+            if !full && source.kind == "qemu" && !source.is_template() {
+                problems.push(format!(
+                    "Source {} isn't a template, which linked clones need",
+                    src
+                ));
+            }
+        }
+    }
+    for resource in &resources {
+        if jobs.contains(&resource.vmid) {
+            problems.push(format!("VMID {} is already in use", resource.vmid));
+        }
+        if let Some(name) = resource.name.as_ref().filter(|n| names.contains(n)) {
+            problems.push(format!(
+                "Name {} is already used by VMID {}",
+                name, resource.vmid
+            ));
+        }
+    }
+    let mut storages = Vec::new();
+    if let Some(source) = source {
+        let config = guest_config(client, headers, url, &source.node, &source.kind, src).await?;
+        storages = disk_usage(&config).into_iter().collect();
+        //Lxcs are always full cloned. Linked clones share the template's disks so barely use any.
+        if full || source.kind == "lxc" {
+            for (storage, size) in &storages {
+                let needed = size * jobs.len() as u64;
+                let avail = storage_avail(client, headers, url, nodename, storage).await?;
+                if needed > avail {
+                    problems.push(format!(
+                        "{} needs {} GiB for {} full clones but only has {} GiB free",
+                        storage,
+                        needed >> 30,
+                        jobs.len(),
+                        avail >> 30
+                    ));
+                }
+            }
+        }
+    }
+    let mut needed = vec![
+        (format!("/vms/{}", src), "VM.Clone"),
+        (String::from("/vms"), "VM.Allocate"),
+    ];
+    for (storage, _) in &storages {
+        needed.push((format!("/storage/{}", storage), "Datastore.AllocateSpace"));
+    }
+    for (path, privilege) in needed {
+        if !privileges(client, headers, url, &path)
+            .await?
+            .contains(privilege)
+        {
+            problems.push(format!("Missing {} on {}", privilege, path));
+        }
+    }
+    if problems.is_empty() {
+        say!("Pre-flight checks passed for {} clones", jobs.len());
+        return Ok(());
+    }
+    for problem in &problems {
+        say!("{}", problem);
+    }
+    Err(format!(
+        "{} pre-flight checks failed, nothing was cloned",
+        problems.len()
+    )
+    .into())
+}
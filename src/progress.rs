@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+//How many lines of the bar are on screen right now. say! clears them before printing so log lines
+//end up above the bar instead of inside it, and the next tick draws it again.
+static DRAWN: Mutex<usize> = Mutex::new(0);
+
+//How many workers get a line of their own before the rest are summed up.
+const MAX_WORKERS: usize = 10;
+
+#[derive(Debug, Default)]
+struct Counts {
+    ok: usize,
+    failed: usize,
+    skipped: usize,
+    //VMIDs being worked on and when they were picked up.
+    active: BTreeMap<i32, Instant>,
+}
+
+//Shows how far a bulk run is. On a terminal it's a bar plus a line per worker redrawn on stderr,
+//anywhere else it's a log line every 10 seconds.
+#[derive(Debug)]
+pub struct Progress {
+    action: String,
+    total: usize,
+    started: Instant,
+    counts: Mutex<Counts>,
+    stopped: AtomicBool,
+}
+
+impl Progress {
+    pub fn start(action: &str, total: usize) -> Arc<Progress> {
+        let progress = Arc::new(Progress {
+            action: action.to_owned(),
+            total,
+            started: Instant::now(),
+            counts: Mutex::new(Counts::default()),
+            stopped: AtomicBool::new(false),
+        });
+        tokio::spawn(ticker(Arc::downgrade(&progress)));
+        progress
+    }
+
+    pub fn begin(&self, vmid: i32) {
+        self.counts
+            .lock()
+            .unwrap()
+            .active
+            .insert(vmid, Instant::now());
+    }
+
+    pub fn end(&self, vmid: i32, status: &str) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.active.remove(&vmid);
+        match status {
+            "ok" => counts.ok += 1,
+            "failed" => counts.failed += 1,
+            _ => counts.skipped += 1,
+        }
+    }
+
+    //Takes the bar off the screen for good, before the summary is printed.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        clear();
+    }
+
+    fn headline(&self, counts: &Counts) -> String {
+        format!(
+            "{}: {}/{} done ({} failed), {} running, {}",
+            self.action,
+            counts.ok + counts.failed + counts.skipped,
+            self.total,
+            counts.failed,
+            counts.active.len(),
+            elapsed(self.started)
+        )
+    }
+
+    fn render(&self) -> Vec<String> {
+        let counts = self.counts.lock().unwrap();
+        let finished = counts.ok + counts.failed + counts.skipped;
+        let width = 30;
+        let filled = (finished * width).checked_div(self.total).unwrap_or(width);
+        let mut lines = vec![format!(
+            "[{}{}] {}",
+            "#".repeat(filled),
+            "-".repeat(width - filled),
+            self.headline(&counts)
+        )];
+        for (vmid, started) in counts.active.iter().take(MAX_WORKERS) {
+            lines.push(format!("  VMID {:<8} {}", vmid, elapsed(*started)));
+        }
+        if counts.active.len() > MAX_WORKERS {
+            lines.push(format!("  and {} more", counts.active.len() - MAX_WORKERS));
+        }
+        lines
+    }
+}
+
+//Runs that bail out before finishing their summary still shouldn't leave a bar behind.
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn elapsed(started: Instant) -> String {
+    let secs = started.elapsed().as_secs_f64();
+    match secs < 60.0 {
+        true => format!("{:.1}s", secs),
+        false => format!("{}m{:02}s", secs as u64 / 60, secs as u64 % 60),
+    }
+}
+
+//Removes the bar if one is drawn. Called by say! before every line it prints.
+pub fn clear() {
+    let mut drawn = DRAWN.lock().unwrap();
+    if *drawn > 0 {
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "\x1b[{}F\x1b[J", *drawn);
+        let _ = stderr.flush();
+        *drawn = 0;
+    }
+}
+
+async fn ticker(progress: Weak<Progress>) {
+    let terminal = std::io::stderr().is_terminal();
+    let interval = match terminal {
+        true => Duration::from_millis(250),
+        false => Duration::from_secs(10),
+    };
+    loop {
+        tokio::time::sleep(interval).await;
+        let progress = match progress.upgrade() {
+            Some(p) => p,
+            None => return,
+        };
+        if progress.stopped.load(Ordering::Relaxed) {
+            return;
+        }
+        if !terminal {
+            //Stderr, so the ticker never ends up mixed into piped output.
+            let headline = progress.headline(&progress.counts.lock().unwrap());
+            eprintln!("{}", headline);
+            continue;
+        }
+        let lines = progress.render();
+        let mut drawn = DRAWN.lock().unwrap();
+        //Checked again under the lock so a bar can't be drawn after stop cleared it.
+        if progress.stopped.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut stderr = std::io::stderr();
+        if *drawn > 0 {
+            let _ = write!(stderr, "\x1b[{}F\x1b[J", *drawn);
+        }
+        for line in &lines {
+            let _ = writeln!(stderr, "{}", line);
+        }
+        let _ = stderr.flush();
+        *drawn = lines.len();
+    }
+}
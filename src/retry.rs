@@ -0,0 +1,138 @@
+use super::throttle;
+use clap::ArgMatches;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+//How many times each VMID had to be retried during this run, for the summary at the end.
+static RETRIES: Mutex<BTreeMap<i32, u32>> = Mutex::new(BTreeMap::new());
+
+//Longest we'll ever wait between two attempts.
+const MAX_DELAY_MS: u64 = 30_000;
+
+//How often and how patiently a failed step is tried again. Delays double after every attempt.
+#[derive(Clone, Copy, Debug)]
+pub struct Retry {
+    pub attempts: u32,
+    pub delay_ms: u64,
+}
+
+impl Retry {
+    pub fn from_args(app: &ArgMatches) -> Retry {
+        let attempts = match app.get_one::<String>("Retries").unwrap().parse::<u32>() {
+            Ok(i) => i,
+            Err(_) => panic!("Retries needs to be a number"),
+        };
+        let delay_ms = match app.get_one::<String>("Retry_delay").unwrap().parse::<u64>() {
+            Ok(i) => i,
+            Err(_) => panic!("Retry_delay needs to be a number of milliseconds"),
+        };
+        Retry { attempts, delay_ms }
+    }
+
+    //Runs op until it works, fails with something retrying won't fix, or runs out of attempts.
+    //Errors are strings so the futures stay Send inside spawned tasks.
+    pub async fn run<T, F, Fut>(&self, vmid: i32, mut op: F) -> Result<T, String>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, String>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match op().await {
+                Ok(t) => return Ok(t),
+                Err(e) if attempt < self.attempts && is_transient(&e) => {
+                    attempt += 1;
+                    let delay = (self.delay_ms << (attempt - 1).min(16)).min(MAX_DELAY_MS);
+                    say!(
+                        "VMID {} failed with {}, retrying in {}ms ({}/{})",
+                        vmid,
+                        e.trim(),
+                        delay,
+                        attempt,
+                        self.attempts
+                    );
+                    *RETRIES.lock().unwrap().entry(vmid).or_insert(0) += 1;
+                    throttle::note_transient_error();
+                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+//Connection problems, 5xx responses and lock timeouts usually go away on their own when the
+//server is busy. Anything saying a guest exists or doesn't won't.
+pub fn is_transient(error: &str) -> bool {
+    let error = error.to_lowercase();
+    if error.contains("already exists") || error.contains("does not exist") {
+        return false;
+    }
+    let server_error = error.len() >= 3
+        && error.starts_with('5')
+        && error[..3].chars().all(|c| c.is_ascii_digit());
+    server_error
+        || error.contains("error sending request")
+        || error.contains("connection")
+        || error.contains("timed out")
+        || error.contains("can't lock file")
+        || error.contains("is locked")
+        || error.contains("got timeout")
+}
+
+//How many times a VMID was retried during this run.
+pub fn count(vmid: i32) -> u32 {
+    RETRIES
+        .lock()
+        .unwrap()
+        .get(&vmid)
+        .copied()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_errors_are_transient() {
+        assert!(is_transient("500 Internal Server Error"));
+        assert!(is_transient("503 Service Unavailable {\"data\":null}"));
+        assert!(is_transient(
+            "596 Unable to convert 101 to a template: Connection timed out"
+        ));
+    }
+
+    #[test]
+    fn client_errors_are_not() {
+        assert!(!is_transient("401 Unauthorized"));
+        assert!(!is_transient(
+            "400 Bad Request {\"errors\":{\"newid\":\"invalid\"}}"
+        ));
+        assert!(!is_transient("5 guests failed"));
+    }
+
+    #[test]
+    fn locks_and_connections_are_transient() {
+        assert!(is_transient(
+            "can't lock file '/var/lock/qemu-server/lock-9000.conf' - got timeout"
+        ));
+        assert!(is_transient("CT 9100 is locked (clone)"));
+        assert!(is_transient(
+            "error sending request for url (https://pve:8006/)"
+        ));
+        assert!(is_transient("operation timed out"));
+    }
+
+    #[test]
+    fn existence_errors_are_never_transient() {
+        assert!(!is_transient(
+            "500 unable to create VM 200: config file already exists"
+        ));
+        assert!(!is_transient(
+            "500 Configuration file 'nodes/pve/qemu-server/201.conf' does not exist"
+        ));
+    }
+}
@@ -0,0 +1,282 @@
+use super::agent::{self, Interface};
+use super::cluster::get_resources;
+use super::manifest::{device_value, value_string};
+use super::mgmt::{guest_config, guest_get};
+use super::output::{self, Format};
+use super::state::format_time;
+use clap::ArgMatches;
+use reqwest::ClientBuilder;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::error::Error;
+
+#[derive(Serialize, Debug)]
+struct Snapshot {
+    name: String,
+    parent: Option<String>,
+    //RFC 3339, left out for snapshots proxmox has no time for.
+    taken: Option<String>,
+    description: String,
+    vmstate: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct Disk {
+    key: String,
+    storage: String,
+    volume: String,
+    size: Option<String>,
+    media: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct Nic {
+    key: String,
+    //Qemu has a model, virtio or e1000, lxc names the interface inside the container instead.
+    model: Option<String>,
+    name: Option<String>,
+    mac: String,
+    bridge: String,
+    vlan: Option<String>,
+    firewall: bool,
+    //Only lxc configs carry the address.
+    ip: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct Details {
+    vmid: i32,
+    name: Option<String>,
+    node: String,
+    #[serde(rename = "type")]
+    kind: String,
+    status: Map<String, Value>,
+    config: Map<String, Value>,
+    snapshots: Vec<Snapshot>,
+    disks: Vec<Disk>,
+    nics: Vec<Nic>,
+    //None when the qemu guest agent didn't answer, which isn't the same as having no addresses.
+    interfaces: Option<Vec<Interface>>,
+    interfaces_error: Option<String>,
+}
+
+//True for keys like scsi0 or mp12, a prefix followed by nothing but a number.
+fn numbered(key: &str, prefixes: &[&str]) -> bool {
+    prefixes.iter().any(|prefix| {
+        key.strip_prefix(prefix)
+            .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+    })
+}
+
+fn disks(kind: &str, config: &Map<String, Value>) -> Vec<Disk> {
+    let prefixes: &[&str] = match kind {
+        "lxc" => &["mp", "unused"],
+        _ => &[
+            "ide", "sata", "scsi", "virtio", "efidisk", "tpmstate", "unused",
+        ],
+    };
+    config
+        .iter()
+        .filter(|(key, _)| *key == "rootfs" || numbered(key, prefixes))
+        .map(|(key, value)| (key, value_string(Some(value))))
+        .filter(|(_, device)| !device.starts_with("none"))
+        .map(|(key, device)| {
+            let volume = device.split(',').next().unwrap_or_default();
+            let (storage, volume) = volume.split_once(':').unwrap_or(("", volume));
+            Disk {
+                key: key.to_owned(),
+                storage: storage.to_owned(),
+                volume: volume.to_owned(),
+                size: Some(device_value(&device, "size")).filter(|s| !s.is_empty()),
+                media: Some(device_value(&device, "media")).filter(|m| !m.is_empty()),
+            }
+        })
+        .collect()
+}
+
+fn nics(kind: &str, config: &Map<String, Value>) -> Vec<Nic> {
+    let some = |value: String| Some(value).filter(|v| !v.is_empty());
+    config
+        .iter()
+        .filter(|(key, _)| numbered(key, &["net"]))
+        .map(|(key, value)| {
+            let device = value_string(Some(value));
+            //Qemu devices start with model=MAC, lxc ones are all plain key=value pairs.
+            let (model, mac) = match kind {
+                "lxc" => (None, device_value(&device, "hwaddr")),
+                _ => match device.split(',').next().unwrap_or_default().split_once('=') {
+                    Some((model, mac)) => (Some(model.to_owned()), mac.to_owned()),
+                    None => (None, String::new()),
+                },
+            };
+            Nic {
+                key: key.to_owned(),
+                model,
+                name: some(device_value(&device, "name")),
+                mac,
+                bridge: device_value(&device, "bridge"),
+                vlan: some(device_value(&device, "tag")),
+                firewall: device_value(&device, "firewall") == "1",
+                ip: some(device_value(&device, "ip")),
+            }
+        })
+        .collect()
+}
+
+fn snapshots(data: Value) -> Vec<Snapshot> {
+    let mut snapshots: Vec<Snapshot> = data
+        .as_array()
+        .unwrap_or(&Vec::new())
+        .iter()
+        //current is where the guest is now, not a snapshot.
+        .filter(|s| s["name"] != "current")
+        .map(|s| Snapshot {
+            name: value_string(s.get("name")),
+            parent: s["parent"].as_str().map(|p| p.to_owned()),
+            taken: s["snaptime"].as_i64().map(format_time),
+            description: value_string(s.get("description")).trim().to_owned(),
+            vmstate: s["vmstate"].as_i64() == Some(1),
+        })
+        .collect();
+    snapshots.sort_by(|a, b| a.taken.cmp(&b.taken));
+    snapshots
+}
+
+//Prints key: value for everything in a map, nested values as json.
+fn print_map(title: &str, map: &Map<String, Value>) {
+    say!("{}", title);
+    let width = map.keys().map(|k| k.len()).max().unwrap_or_default();
+    for (key, value) in map {
+        say!(
+            "  {:<width$}  {}",
+            key,
+            value_string(Some(value)),
+            width = width
+        );
+    }
+}
+
+fn print_details(details: &Details) {
+    say!(
+        "VMID {} ({}), {} on {}",
+        details.vmid,
+        details.name.as_deref().unwrap_or("no name"),
+        details.kind,
+        details.node
+    );
+    print_map("Status", &details.status);
+    print_map("Config", &details.config);
+    say!("Snapshots");
+    if details.snapshots.is_empty() {
+        say!("  none");
+    }
+    for s in &details.snapshots {
+        say!(
+            "  {}  {}  {}{}",
+            s.name,
+            s.taken.as_deref().unwrap_or("-"),
+            s.description,
+            if s.vmstate { " (with RAM)" } else { "" }
+        );
+    }
+    say!("Disks");
+    for d in &details.disks {
+        say!(
+            "  {:<10} {:<12} {:<30} {}",
+            d.key,
+            d.storage,
+            d.volume,
+            d.media
+                .clone()
+                .or(d.size.clone())
+                .unwrap_or_else(|| String::from("-"))
+        );
+    }
+    say!("Network");
+    for n in &details.nics {
+        let mut parts = vec![
+            n.model.clone().or(n.name.clone()).unwrap_or_default(),
+            n.mac.clone(),
+            n.bridge.clone(),
+        ];
+        if let Some(vlan) = &n.vlan {
+            parts.push(format!("vlan {}", vlan));
+        }
+        if n.firewall {
+            parts.push(String::from("firewall"));
+        }
+        if let Some(ip) = &n.ip {
+            parts.push(ip.to_owned());
+        }
+        parts.retain(|p| !p.is_empty());
+        say!("  {:<10} {}", n.key, parts.join("  "));
+    }
+    say!("Addresses");
+    match (&details.interfaces, &details.interfaces_error) {
+        (Some(interfaces), _) => {
+            for i in interfaces.iter().filter(|i| !i.is_loopback()) {
+                say!(
+                    "  {:<10} {:<18} {}",
+                    i.name,
+                    i.mac.as_deref().unwrap_or("-"),
+                    i.addresses.join(" ")
+                );
+            }
+        }
+        (None, Some(error)) => say!("  unknown, {}", error),
+        (None, None) => say!("  unknown"),
+    }
+}
+
+//Everything proxmox knows about one guest, wherever in the cluster it is.
+pub async fn show(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let vmid = *app
+        .get_one::<i32>("Source")
+        .unwrap_or_else(|| panic!("show needs a --source"));
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let resource = get_resources(&client, &token, &url)
+        .await?
+        .into_iter()
+        .find(|r| r.vmid == vmid)
+        .ok_or_else(|| format!("VMID {} doesn't exist in the cluster", vmid))?;
+    let (node, kind) = (resource.node.as_str(), resource.kind.as_str());
+    let id = vmid.to_string();
+    let status = guest_get(&client, &token, &url, node, kind, &id, "status/current").await?;
+    let config = guest_config(&client, &token, &url, node, kind, &id).await?;
+    let snapshot_data = guest_get(&client, &token, &url, node, kind, &id, "snapshot").await?;
+    //Stopped guests have nothing to report and qemu ones may not run the agent at all.
+    let (interfaces, interfaces_error) = match status["status"] == "running" {
+        true => match agent::interfaces(&client, &token, &url, node, kind, vmid).await {
+            Ok(interfaces) => (Some(interfaces), None),
+            Err(e) => (None, Some(e.to_string())),
+        },
+        false => (None, Some(String::from("guest isn't running"))),
+    };
+    let details = Details {
+        vmid,
+        name: resource.name.clone(),
+        node: node.to_owned(),
+        kind: kind.to_owned(),
+        status: status.as_object().cloned().unwrap_or_default(),
+        disks: disks(kind, &config),
+        nics: nics(kind, &config),
+        config,
+        snapshots: snapshots(snapshot_data),
+        interfaces,
+        interfaces_error,
+    };
+    match output::format() {
+        Format::Text => print_details(&details),
+        _ => output::records(&[details])?,
+    }
+    Ok(())
+}
@@ -0,0 +1,401 @@
+use super::cluster::{get_resources, Resource};
+use super::mgmt::{destroy_guest, guest_status, stop_guest};
+use super::output;
+use super::summary::Summary;
+use clap::ArgMatches;
+use reqwest::ClientBuilder;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+//Bulk tasks record guests at the same time, so writes to the state file go one at a time.
+static STATE_LOCK: Mutex<()> = Mutex::new(());
+static RUN_ID: OnceLock<String> = OnceLock::new();
+
+//Everything terrabad has created and not destroyed yet.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct State {
+    #[serde(default)]
+    pub resources: Vec<Entry>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Entry {
+    pub vmid: i32,
+    pub node: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub source: Option<String>,
+    pub name: String,
+    pub created: String,
+    pub run: String,
+}
+
+//Where the state lives and which run new entries belong to. Manifest runs use the lab name so
+//everything from the same manifest stays together across runs.
+#[derive(Clone, Debug)]
+pub struct StateFile {
+    pub path: String,
+    pub run: String,
+}
+
+impl StateFile {
+    pub fn from_args(app: &ArgMatches) -> StateFile {
+        StateFile {
+            path: app.get_one::<String>("State").unwrap().to_owned(),
+            run: run_id(),
+        }
+    }
+
+    pub fn for_manifest(&self, lab: &str) -> StateFile {
+        StateFile {
+            path: self.path.clone(),
+            run: format!("manifest:{}", lab),
+        }
+    }
+
+    pub fn load(&self) -> Result<State, Box<dyn Error>> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(text) => Ok(serde_json::from_str::<State>(&text)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    //Writes to a temp file first so a crash halfway through doesn't eat the state.
+    pub fn save(&self, state: &State) -> Result<(), Box<dyn Error>> {
+        let tmp = format!("{}.tmp", self.path);
+        std::fs::write(&tmp, serde_json::to_string_pretty(state)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    //Adds a guest to the state, replacing anything already recorded under its VMID.
+    pub fn record(
+        &self,
+        vmid: i32,
+        node: &str,
+        kind: &str,
+        source: Option<&str>,
+        name: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let _lock = STATE_LOCK.lock().unwrap();
+        let mut state = self.load()?;
+        state.resources.retain(|e| e.vmid != vmid);
+        state.resources.push(Entry {
+            vmid,
+            node: node.to_owned(),
+            kind: kind.to_owned(),
+            source: source.map(|s| s.to_owned()),
+            name: name.to_owned(),
+            created: timestamp(),
+            run: self.run.clone(),
+        });
+        state.resources.sort_by_key(|e| e.vmid);
+        self.save(&state)
+    }
+
+    //Drops a guest from the state once it has been destroyed.
+    pub fn forget(&self, vmid: i32) -> Result<(), Box<dyn Error>> {
+        let _lock = STATE_LOCK.lock().unwrap();
+        let mut state = self.load()?;
+        let before = state.resources.len();
+        state.resources.retain(|e| e.vmid != vmid);
+        if state.resources.len() != before {
+            self.save(&state)?;
+        }
+        Ok(())
+    }
+}
+
+//Every invocation that isn't driven by a manifest gets its own run id.
+pub fn run_id() -> String {
+    RUN_ID
+        .get_or_init(|| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            format!("run-{}-{}", now.as_secs(), std::process::id())
+        })
+        .to_owned()
+}
+
+pub fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    format_time(secs)
+}
+
+//Seconds since the epoch as UTC time in RFC 3339 form. Days are turned into a date with Howard
+//Hinnant's civil_from_days so we don't need a date crate for a few timestamps.
+pub fn format_time(secs: i64) -> String {
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+//Entries matching --run, or all of them.
+fn selected(app: &ArgMatches, state: &State) -> Vec<Entry> {
+    let run = app.get_one::<String>("Run");
+    state
+        .resources
+        .iter()
+        .filter(|e| run.is_none_or(|r| &e.run == r))
+        .cloned()
+        .collect()
+}
+
+//Prints what the state file knows about. Doesn't need to talk to proxmox.
+pub fn state_list(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let state_file = StateFile::from_args(&app);
+    let state = state_file.load()?;
+    if output::machine() {
+        return output::records(&selected(&app, &state));
+    }
+    say!(
+        "{:<8} {:<12} {:<6} {:<8} {:<20} {:<22} RUN",
+        "VMID",
+        "NODE",
+        "TYPE",
+        "SOURCE",
+        "NAME",
+        "CREATED"
+    );
+    for entry in selected(&app, &state) {
+        say!(
+            "{:<8} {:<12} {:<6} {:<8} {:<20} {:<22} {}",
+            entry.vmid,
+            entry.node,
+            entry.kind,
+            entry.source.unwrap_or_else(|| String::from("-")),
+            entry.name,
+            entry.created,
+            entry.run
+        );
+    }
+    Ok(())
+}
+
+//Syncs the state with the cluster. Guests that moved nodes or were renamed are updated and ones
+//that no longer exist are dropped.
+pub async fn state_refresh(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let state_file = StateFile::from_args(&app);
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let resources = get_resources(&client, &token, &url).await?;
+    let _lock = STATE_LOCK.lock().unwrap();
+    let mut state = state_file.load()?;
+    let mut kept = Vec::new();
+    for mut entry in state.resources {
+        match resources.iter().find(|r| r.vmid == entry.vmid) {
+            Some(resource) => {
+                if resource.node != entry.node {
+                    say!(
+                        "VMID {} moved from {} to {}",
+                        entry.vmid,
+                        entry.node,
+                        resource.node
+                    );
+                    entry.node = resource.node.clone();
+                }
+                if let Some(name) = resource.name.as_ref().filter(|n| **n != entry.name) {
+                    say!(
+                        "VMID {} renamed from {} to {}",
+                        entry.vmid,
+                        entry.name,
+                        name
+                    );
+                    entry.name = name.clone();
+                }
+                entry.kind = resource.kind.clone();
+                kept.push(entry);
+            }
+            None => say!(
+                "VMID {} no longer exists, removing it from the state",
+                entry.vmid
+            ),
+        }
+    }
+    state.resources = kept;
+    state_file.save(&state)?;
+    say!("{} guests in state", state.resources.len());
+    output::records(&state.resources)
+}
+
+//Why the guest at an entry's VMID might not be the one terrabad created, if it might not be. The
+//VMID could have been freed and reused since, so the name and node have to match, and guests from a
+//manifest have to carry its lab tag. Entries without a recorded name are checked on node alone.
+fn mismatch(entry: &Entry, resources: &[Resource]) -> Option<String> {
+    let resource = match resources.iter().find(|r| r.vmid == entry.vmid) {
+        Some(r) => r,
+        None => return Some(String::from("no longer exists")),
+    };
+    let name = resource.name.clone().unwrap_or_default();
+    if resource.node != entry.node || (!entry.name.is_empty() && name != entry.name) {
+        return Some(format!(
+            "is {} on {} now, not {} on {}",
+            name, resource.node, entry.name, entry.node
+        ));
+    }
+    if let Some(lab) = entry.run.strip_prefix("manifest:") {
+        if !resource.tag_list().iter().any(|t| t == lab) {
+            return Some(format!("doesn't carry the {} tag", lab));
+        }
+    }
+    None
+}
+
+//Destroys every guest in the state, or just the ones from --run, stopping them first if needed.
+//Guests that don't match their entry anymore are skipped and reported, never destroyed. Running
+//state_refresh first picks up guests that were only moved or renamed.
+pub async fn state_destroy(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let state_file = StateFile::from_args(&app);
+    let entries = selected(&app, &state_file.load()?);
+    if entries.is_empty() {
+        say!("Nothing to destroy.");
+        return Summary::single("state_destroy").finish();
+    }
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let resources = get_resources(&client, &token, &url).await?;
+    let (entries, mismatched): (Vec<_>, Vec<_>) = entries
+        .into_iter()
+        .map(|e| {
+            let reason = mismatch(&e, &resources);
+            (e, reason)
+        })
+        .partition(|(_, reason)| reason.is_none());
+    for (entry, _) in &entries {
+        say!("- {} {} on {}", entry.vmid, entry.name, entry.node);
+    }
+    for (entry, reason) in &mismatched {
+        say!(
+            "! {} {} {}, skipping it",
+            entry.vmid,
+            entry.name,
+            reason.clone().unwrap_or_default()
+        );
+    }
+    let question = format!("Destroy these {} guests?", entries.len());
+    if entries.is_empty() {
+        say!("Nothing to destroy.");
+    } else if !app.get_flag("Auto_approve") && !output::confirm(&question)? {
+        say!("Destroy cancelled.");
+        return Summary::single("state_destroy").finish();
+    }
+    let summary = Summary::with_progress("state_destroy", entries.len() + mismatched.len());
+    for (entry, reason) in mismatched {
+        let mut outcome = summary.start(entry.vmid, &entry.node);
+        outcome.kind = Some(entry.kind.clone());
+        summary.skipped(outcome, &reason.unwrap_or_default());
+    }
+    for (entry, _) in entries {
+        let mut outcome = summary.start(entry.vmid, &entry.node);
+        outcome.kind = Some(entry.kind.clone());
+        let vmid = entry.vmid.to_string();
+        let result = async {
+            if guest_status(&client, &token, &url, &entry.node, &entry.kind, &vmid).await?
+                == "running"
+            {
+                stop_guest(&client, &token, &url, &entry.node, &entry.kind, &vmid).await?;
+            }
+            destroy_guest(&client, &token, &url, &entry.node, &vmid).await
+        }
+        .await;
+        match result {
+            Ok(upid) => {
+                state_file.forget(entry.vmid)?;
+                say!("{} destroyed", entry.vmid);
+                outcome.upid = Some(upid);
+                summary.ok(outcome);
+            }
+            Err(e) => {
+                say!("Unable to destroy {}: {}", entry.vmid, e);
+                summary.failed(outcome, &e.to_string());
+            }
+        }
+    }
+    summary.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_unix_time_as_utc() {
+        assert_eq!(format_time(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_time(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_time(1_700_000_000), "2023-11-14T22:13:20Z");
+        assert_eq!(format_time(-1), "1969-12-31T23:59:59Z");
+    }
+
+    fn entry(name: &str, run: &str) -> Entry {
+        Entry {
+            vmid: 150,
+            node: String::from("pve"),
+            kind: String::from("qemu"),
+            source: None,
+            name: name.to_owned(),
+            created: format_time(0),
+            run: run.to_owned(),
+        }
+    }
+
+    fn resource(name: Option<&str>, tags: &str) -> Resource {
+        serde_json::from_value(serde_json::json!({
+            "vmid": 150,
+            "node": "pve",
+            "type": "qemu",
+            "name": name,
+            "tags": tags,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn only_unchanged_guests_match_their_entry() {
+        let live = [resource(Some("old"), "ctf")];
+        assert_eq!(mismatch(&entry("old", "run-1"), &live), None);
+        assert!(mismatch(&entry("new", "run-1"), &live).is_some());
+        assert_eq!(mismatch(&entry("old", "manifest:ctf"), &live), None);
+        assert!(mismatch(&entry("old", "manifest:web"), &live).is_some());
+        assert!(mismatch(&entry("old", "run-1"), &[]).is_some());
+        //Unnamed guests are recorded with an empty name, which matches whatever they're called.
+        let unnamed = [resource(None, "")];
+        assert_eq!(mismatch(&entry("", "run-1"), &unnamed), None);
+    }
+}
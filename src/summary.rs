@@ -0,0 +1,199 @@
+use super::output::{self, Format};
+use super::progress::Progress;
+use super::retry;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+//How one VMID of a bulk run went. Status is ok, failed or skipped.
+#[derive(Serialize, Debug, Clone)]
+pub struct Outcome {
+    pub vmid: i32,
+    pub node: String,
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub action: String,
+    pub status: String,
+    pub seconds: f64,
+    pub retries: u32,
+    pub upid: Option<String>,
+    pub error: Option<String>,
+    //Fields only some actions have, like what exec printed. Written after everything else.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub extra: Option<Map<String, Value>>,
+    #[serde(skip)]
+    started: Option<Instant>,
+}
+
+//Returned when some of a bulk run failed so main can exit with its own code.
+#[derive(Debug)]
+pub struct PartialFailure {
+    pub failed: usize,
+    pub total: usize,
+}
+
+impl fmt::Display for PartialFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} of {} guests failed", self.failed, self.total)
+    }
+}
+
+impl Error for PartialFailure {}
+
+//Collects the outcome of every VMID a bulk action touched and prints them as a table at the end.
+//With --output json or ndjson the outcomes are the records scripts read instead.
+#[derive(Clone, Debug)]
+pub struct Summary {
+    action: String,
+    started: Instant,
+    outcomes: Arc<Mutex<Vec<Outcome>>>,
+    single: bool,
+    progress: Option<Arc<Progress>>,
+}
+
+impl Summary {
+    pub fn new(action: &str) -> Summary {
+        Summary {
+            action: action.to_owned(),
+            started: Instant::now(),
+            outcomes: Arc::new(Mutex::new(Vec::new())),
+            single: false,
+            progress: None,
+        }
+    }
+
+    //For bulk runs big enough to want a progress display while they go.
+    pub fn with_progress(action: &str, total: usize) -> Summary {
+        Summary {
+            progress: Some(Progress::start(action, total)),
+            ..Summary::new(action)
+        }
+    }
+
+    //For actions on one guest. They already say what happened, so text mode skips the table and
+    //a failure comes back as the error itself.
+    pub fn single(action: &str) -> Summary {
+        Summary {
+            single: true,
+            ..Summary::new(action)
+        }
+    }
+
+    //Starts the clock on one VMID. Fill in kind and upid as they become known.
+    pub fn start(&self, vmid: i32, node: &str) -> Outcome {
+        if let Some(progress) = &self.progress {
+            progress.begin(vmid);
+        }
+        Outcome {
+            vmid,
+            node: node.to_owned(),
+            kind: None,
+            action: self.action.clone(),
+            status: String::new(),
+            seconds: 0.0,
+            retries: 0,
+            upid: None,
+            error: None,
+            extra: None,
+            started: Some(Instant::now()),
+        }
+    }
+
+    pub fn record(&self, mut outcome: Outcome, status: &str) {
+        outcome.status = status.to_owned();
+        outcome.seconds = outcome
+            .started
+            .map(|s| s.elapsed().as_secs_f64())
+            .unwrap_or_default();
+        outcome.retries = retry::count(outcome.vmid);
+        if let Some(progress) = &self.progress {
+            progress.end(outcome.vmid, status);
+        }
+        if output::format() == Format::Ndjson {
+            output::line(&outcome);
+        }
+        self.outcomes.lock().unwrap().push(outcome);
+    }
+
+    pub fn ok(&self, outcome: Outcome) {
+        self.record(outcome, "ok");
+    }
+
+    pub fn failed(&self, mut outcome: Outcome, error: &str) {
+        outcome.error = Some(error.to_owned());
+        self.record(outcome, "failed");
+    }
+
+    pub fn skipped(&self, mut outcome: Outcome, reason: &str) {
+        outcome.error = Some(reason.to_owned());
+        self.record(outcome, "skipped");
+    }
+
+    //Prints the table and totals, or the json array. Ndjson records went out as they happened.
+    //Any failure turns into a PartialFailure error.
+    pub fn finish(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(progress) = &self.progress {
+            progress.stop();
+        }
+        let mut outcomes = self.outcomes.lock().unwrap().clone();
+        outcomes.sort_by_key(|o| o.vmid);
+        let count = |status: &str| outcomes.iter().filter(|o| o.status == status).count();
+        let failed = count("failed");
+        if self.single {
+            if matches!(output::format(), Format::Json | Format::Csv) {
+                output::records(&outcomes)?;
+            }
+            return match outcomes.iter().find(|o| o.status == "failed") {
+                Some(outcome) => Err(outcome.error.clone().unwrap_or_default().into()),
+                None => Ok(()),
+            };
+        }
+        match output::format() {
+            Format::Json | Format::Csv => output::records(&outcomes)?,
+            Format::Ndjson => {}
+            Format::Text => {
+                say!();
+                say!(
+                    "{:<8} {:<8} {:>8} {:>7}  UPID / ERROR",
+                    "VMID",
+                    "RESULT",
+                    "SECONDS",
+                    "RETRIES"
+                );
+                for outcome in &outcomes {
+                    let detail = match (&outcome.error, &outcome.upid) {
+                        (Some(e), _) => e.trim().to_owned(),
+                        (None, Some(upid)) => upid.to_owned(),
+                        (None, None) => String::from("-"),
+                    };
+                    say!(
+                        "{:<8} {:<8} {:>8.1} {:>7}  {}",
+                        outcome.vmid,
+                        outcome.status,
+                        outcome.seconds,
+                        outcome.retries,
+                        detail
+                    );
+                }
+            }
+        }
+        say!(
+            "{}: {} ok, {} failed, {} skipped in {:.1}s",
+            self.action,
+            count("ok"),
+            failed,
+            count("skipped"),
+            self.started.elapsed().as_secs_f64()
+        );
+        if failed > 0 {
+            return Err(Box::new(PartialFailure {
+                failed,
+                total: outcomes.len(),
+            }));
+        }
+        Ok(())
+    }
+}
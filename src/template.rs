@@ -0,0 +1,243 @@
+use super::hardware::Hardware;
+use super::mgmt::{exit_status, guest_type, submit_task, UPIDData};
+use super::state::StateFile;
+use super::summary::Summary;
+use clap::ArgMatches;
+use reqwest::header::HeaderMap;
+use reqwest::multipart::{Form, Part};
+use reqwest::{Body, Client, ClientBuilder};
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::path::Path;
+
+//Turns a guest into a template. Qemu hands back a upid for this, lxc just returns once it's done.
+pub async fn convert_to_template(
+    client: &Client,
+    token: &HeaderMap,
+    url: &str,
+    nodename: &str,
+    vmid: &str,
+) -> Result<(), Box<dyn Error>> {
+    let kind = guest_type(client, token, url, nodename, vmid).await?;
+    let template_url = format!(
+        "{}/api2/json/nodes/{}/{}/{}/template",
+        url, nodename, kind, vmid
+    );
+    let response = client
+        .post(template_url)
+        .headers(token.clone())
+        .send()
+        .await?;
+    //The status goes first so is_transient can spot a 5xx.
+    if response.status() != 200 {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        return Err(format!(
+            "{} Unable to convert {} to a template: {}",
+            status, vmid, text
+        )
+        .into());
+    }
+    if let Ok(upid) = serde_json::de::from_str::<UPIDData>(response.text().await?.as_str()) {
+        let status = exit_status(token.clone(), upid, url, nodename).await?;
+        if status != "OK" {
+            return Err(format!("Converting {} to a template failed: {}", vmid, status).into());
+        }
+    }
+    Ok(())
+}
+
+//Converts an existing VM or LXC into a template.
+pub async fn template(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let nodename = app.get_one::<String>("Node").unwrap();
+    let src = match app.get_one::<i32>("Source") {
+        Some(s) => s.to_string(),
+        None => panic!("This action requires a source VMID"),
+    };
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let summary = Summary::single("template");
+    let mut outcome = summary.start(*app.get_one::<i32>("Source").unwrap(), nodename);
+    match convert_to_template(&client, &token, &url, nodename, &src).await {
+        Ok(_) => {
+            say!("VMID {} converted to a template", src);
+            outcome.kind = guest_type(&client, &token, &url, nodename, &src).await.ok();
+            summary.ok(outcome);
+        }
+        Err(e) => summary.failed(outcome, &e.to_string()),
+    }
+    summary.finish()
+}
+
+//Uploads a local disk image to a storage with the import content type and returns the volid it
+//ended up as. If the image isn't a local file but looks like a volid (local:import/disk.qcow2)
+//we assume it's already been uploaded and use it as is.
+async fn upload_image(
+    client: &Client,
+    token: &HeaderMap,
+    url: &str,
+    nodename: &str,
+    storage: &str,
+    image: &str,
+) -> Result<String, Box<dyn Error>> {
+    let path = Path::new(image);
+    if !path.is_file() {
+        if image.contains(':') {
+            return Ok(image.to_owned());
+        }
+        return Err(format!("{} is not a file or a volid", image).into());
+    }
+    let filename = match path.file_name() {
+        Some(f) => f.to_string_lossy().to_string(),
+        None => return Err(format!("{} has no file name", image).into()),
+    };
+    let file = tokio::fs::File::open(path).await?;
+    let length = file.metadata().await?.len();
+    //Streams the image so multi gigabyte disks don't have to fit in memory.
+    let part = Part::stream_with_length(Body::from(file), length).file_name(filename.clone());
+    let form = Form::new().text("content", "import").part("filename", part);
+    let upload_url = format!(
+        "{}/api2/json/nodes/{}/storage/{}/upload",
+        url, nodename, storage
+    );
+    say!("Uploading {} to {}", filename, storage);
+    let upid = submit_task(
+        client
+            .post(upload_url)
+            .headers(token.clone())
+            .multipart(form),
+    )
+    .await?;
+    let status = exit_status(token.clone(), upid, url, nodename).await?;
+    if status != "OK" {
+        return Err(format!("Uploading {} failed: {}", filename, status).into());
+    }
+    Ok(format!("{}:import/{}", storage, filename))
+}
+
+//Builds a template from a qcow2/raw disk image. The image is uploaded, imported as the boot disk
+//of a new VM, a cloud-init drive is attached and the whole thing is converted to a template.
+pub async fn template_build(app: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let nodename = app.get_one::<String>("Node").unwrap();
+    let dst = match app.get_one::<i32>("Destination") {
+        Some(d) => *d,
+        None => panic!("This action requires a destination VMID"),
+    };
+    let summary = Summary::single("template_build");
+    let mut outcome = summary.start(dst, nodename);
+    match build_template(&app, nodename, dst).await {
+        Ok(upid) => {
+            outcome.kind = Some(String::from("qemu"));
+            outcome.upid = Some(upid);
+            summary.ok(outcome);
+        }
+        Err(e) => summary.failed(outcome, &e.to_string()),
+    }
+    summary.finish()
+}
+
+//Does the work of template_build and hands back the upid of the create.
+async fn build_template(
+    app: &ArgMatches,
+    nodename: &str,
+    dst: i32,
+) -> Result<String, Box<dyn Error>> {
+    let image = match app.get_one::<String>("Image") {
+        Some(i) => i,
+        None => panic!("This action requires a disk image"),
+    };
+    let storage = app.get_one::<String>("Storage").unwrap();
+    let import_storage = app.get_one::<String>("Import_storage").unwrap();
+    let mut url = app.get_one::<String>("Url").unwrap().to_owned();
+    if url.ends_with('/') {
+        url.pop();
+    }
+    let username = app.get_one::<String>("Username").unwrap();
+    let password = app.get_one::<String>("Password").unwrap();
+    let token = super::auth::get_token(&mut username.clone(), password, &url).await?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let volid = upload_image(&client, &token, &url, nodename, import_storage, image).await?;
+
+    let mut json_data = Map::new();
+    json_data.insert("vmid".to_string(), Value::String(dst.to_string()));
+    if let Some(name) = app.get_one::<String>("Name") {
+        json_data.insert("name".to_string(), Value::String(name.to_owned()));
+    }
+    //The hardware options double as the specs of the template, with some small defaults.
+    let mut hardware = Hardware::from_args(app);
+    hardware.memory.get_or_insert(String::from("2048"));
+    hardware.cores.get_or_insert(String::from("2"));
+    hardware.bridge.get_or_insert(String::from("vmbr0"));
+    for (key, value) in [
+        ("memory", &hardware.memory),
+        ("cores", &hardware.cores),
+        ("sockets", &hardware.sockets),
+        ("balloon", &hardware.balloon),
+    ] {
+        if let Some(value) = value {
+            json_data.insert(key.to_string(), Value::String(value.to_owned()));
+        }
+    }
+    json_data.insert(
+        hardware.net.clone(),
+        Value::String(hardware.net_string("qemu", None)?),
+    );
+    json_data.insert(
+        "scsihw".to_string(),
+        Value::String("virtio-scsi-pci".to_string()),
+    );
+    json_data.insert(
+        "scsi0".to_string(),
+        Value::String(format!("{}:0,import-from={}", storage, volid)),
+    );
+    json_data.insert(
+        "ide2".to_string(),
+        Value::String(format!("{}:cloudinit", storage)),
+    );
+    json_data.insert("boot".to_string(), Value::String("order=scsi0".to_string()));
+    //Most cloud images expect a serial console and ship with the guest agent.
+    json_data.insert("serial0".to_string(), Value::String("socket".to_string()));
+    json_data.insert("vga".to_string(), Value::String("serial0".to_string()));
+    json_data.insert("agent".to_string(), Value::String("1".to_string()));
+    let create_url = format!("{}/api2/json/nodes/{}/qemu", url, nodename);
+    let upid = submit_task(
+        client
+            .post(create_url)
+            .headers(token.clone())
+            .json(&json_data),
+    )
+    .await?;
+    let id = upid.data.clone();
+    let status = exit_status(token.clone(), upid, &url, nodename).await?;
+    if status != "OK" {
+        return Err(format!("Creating VMID {} failed: {}", dst, status).into());
+    }
+    say!("VMID {} created from {}", dst, volid);
+    //The imported disk is only as big as the image, so it's grown before it becomes a template.
+    if hardware.disk_size.is_some() {
+        let resize = Hardware {
+            net: hardware.net.clone(),
+            disk: hardware.disk.clone(),
+            disk_size: hardware.disk_size.clone(),
+            ..Default::default()
+        };
+        resize
+            .apply(&client, &token, &url, nodename, "qemu", &dst.to_string())
+            .await?;
+    }
+    let name = app.get_one::<String>("Name").map_or("", |n| n.as_str());
+    StateFile::from_args(app).record(dst, nodename, "qemu", Some(&volid), name)?;
+    convert_to_template(&client, &token, &url, nodename, &dst.to_string()).await?;
+    say!("VMID {} converted to a template", dst);
+    Ok(id)
+}
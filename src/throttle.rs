@@ -0,0 +1,221 @@
+use clap::ArgMatches;
+use reqwest::header::HeaderMap;
+use reqwest::Client;
+use serde::Deserialize;
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+//Lock and timeout errors seen by the retry code. The throttle backs off when this goes up.
+static TRANSIENT_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+//How often the adaptive mode looks at the numbers and changes the number of workers.
+const INTERVAL: Duration = Duration::from_secs(3);
+
+pub fn note_transient_error() {
+    TRANSIENT_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+//Handles /nodes/{node}/status. Both are fractions, so 0.5 is 50% cpu or 50% of time in io wait.
+#[derive(Deserialize, Debug)]
+struct NodeStatusData {
+    data: NodeStatus,
+}
+
+#[derive(Deserialize, Debug)]
+struct NodeStatus {
+    #[serde(default)]
+    cpu: f64,
+    #[serde(default)]
+    wait: f64,
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    limit: usize,
+    //Permits to throw away instead of handing back, which is how the limit shrinks while jobs hold
+    //them.
+    debt: usize,
+    finished: usize,
+    //Average job time over the last interval and the best seen, in seconds.
+    latency: f64,
+    best: f64,
+}
+
+//A semaphore whose size can change while a bulk run is going. With a fixed --threads it's the
+//plain semaphore every bulk action used before. With --adaptive it starts at one worker and a
+//monitor grows or shrinks it, never going past --threads.
+pub struct Throttle {
+    semaphore: Arc<Semaphore>,
+    stats: Mutex<Stats>,
+    max: usize,
+}
+
+//Holds a worker slot until the job is done.
+pub struct Slot {
+    permit: Option<OwnedSemaphorePermit>,
+    throttle: Arc<Throttle>,
+    started: Instant,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let mut stats = self.throttle.stats.lock().unwrap();
+        stats.finished += 1;
+        stats.latency = if stats.latency == 0.0 {
+            elapsed
+        } else {
+            stats.latency * 0.7 + elapsed * 0.3
+        };
+        if stats.debt > 0 {
+            stats.debt -= 1;
+            if let Some(permit) = self.permit.take() {
+                permit.forget();
+            }
+        }
+    }
+}
+
+impl Throttle {
+    pub async fn from_args(
+        app: &ArgMatches,
+        client: &Client,
+        token: &HeaderMap,
+        url: &str,
+    ) -> Result<Arc<Throttle>, Box<dyn Error>> {
+        let max = app.get_one::<String>("Threads").unwrap().parse::<usize>()?;
+        if !app.get_flag("Adaptive") || max <= 1 {
+            return Ok(Throttle::fixed(max));
+        }
+        let throttle = Arc::new(Throttle {
+            semaphore: Arc::new(Semaphore::new(1)),
+            stats: Mutex::new(Stats {
+                limit: 1,
+                ..Default::default()
+            }),
+            max,
+        });
+        say!(
+            "Adaptive concurrency, starting at 1 worker with a max of {}",
+            max
+        );
+        let nodename = app.get_one::<String>("Node").unwrap().to_owned();
+        tokio::spawn(monitor(
+            Arc::downgrade(&throttle),
+            client.clone(),
+            token.clone(),
+            url.to_owned(),
+            nodename,
+        ));
+        Ok(throttle)
+    }
+
+    pub fn fixed(workers: usize) -> Arc<Throttle> {
+        Arc::new(Throttle {
+            semaphore: Arc::new(Semaphore::new(workers)),
+            stats: Mutex::new(Stats {
+                limit: workers,
+                ..Default::default()
+            }),
+            max: workers,
+        })
+    }
+
+    pub async fn acquire(self: &Arc<Self>) -> Slot {
+        let permit = self.semaphore.clone().acquire_owned().await.unwrap();
+        Slot {
+            permit: Some(permit),
+            throttle: self.clone(),
+            started: Instant::now(),
+        }
+    }
+
+    fn set_limit(&self, stats: &mut Stats, limit: usize) {
+        if limit > stats.limit {
+            let mut grow = limit - stats.limit;
+            //Cancel out permits that were waiting to be thrown away first.
+            let cancelled = grow.min(stats.debt);
+            stats.debt -= cancelled;
+            grow -= cancelled;
+            self.semaphore.add_permits(grow);
+        } else {
+            for _ in limit..stats.limit {
+                match self.semaphore.try_acquire() {
+                    Ok(permit) => permit.forget(),
+                    Err(_) => stats.debt += 1,
+                }
+            }
+        }
+        say!("Concurrency {} -> {}", stats.limit, limit);
+        stats.limit = limit;
+    }
+}
+
+async fn node_status(
+    client: &Client,
+    token: &HeaderMap,
+    url: &str,
+    nodename: &str,
+) -> Result<NodeStatus, Box<dyn Error>> {
+    let status_url = format!("{}/api2/json/nodes/{}/status", url, nodename);
+    let text = client
+        .get(status_url)
+        .headers(token.clone())
+        .send()
+        .await?
+        .text()
+        .await?;
+    Ok(serde_json::de::from_str::<NodeStatusData>(text.as_str())?.data)
+}
+
+//Additive increase, multiplicative decrease. Any lock or timeout error, or a node that's pegged,
+//halves the workers. Jobs taking twice as long as the best seen drops one. Otherwise a worker is
+//added when the last interval got work done without slowing down.
+async fn monitor(
+    throttle: Weak<Throttle>,
+    client: Client,
+    token: HeaderMap,
+    url: String,
+    nodename: String,
+) {
+    let mut errors_seen = TRANSIENT_ERRORS.load(Ordering::Relaxed);
+    loop {
+        tokio::time::sleep(INTERVAL).await;
+        let throttle = match throttle.upgrade() {
+            Some(t) => t,
+            None => return,
+        };
+        //A node we can't read is treated as busy so we don't pile on.
+        let node = node_status(&client, &token, &url, &nodename)
+            .await
+            .map_err(|e| e.to_string());
+        let (cpu, wait) = match node {
+            Ok(n) => (n.cpu, n.wait),
+            Err(_) => (1.0, 1.0),
+        };
+        let errors = TRANSIENT_ERRORS.load(Ordering::Relaxed);
+        let new_errors = errors - errors_seen;
+        errors_seen = errors;
+        let mut stats = throttle.stats.lock().unwrap();
+        let finished = std::mem::take(&mut stats.finished);
+        if finished > 0 && (stats.best == 0.0 || stats.latency < stats.best) {
+            stats.best = stats.latency;
+        }
+        let limit = stats.limit;
+        let wanted = if new_errors > 0 || cpu > 0.9 || wait > 0.25 {
+            (limit / 2).max(1)
+        } else if finished > 0 && stats.latency > stats.best * 2.0 {
+            (limit - 1).max(1)
+        } else if finished > 0 && cpu < 0.75 && wait < 0.1 {
+            (limit + 1).min(throttle.max)
+        } else {
+            limit
+        };
+        if wanted != limit {
+            throttle.set_limit(&mut stats, wanted);
+        }
+    }
+}